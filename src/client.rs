@@ -1,23 +1,102 @@
 use cached::proc_macro::cached;
 use futures_lite::{future::Boxed, FutureExt};
 use hyper::client::HttpConnector;
-use hyper::{body, body::Buf, client, header, Body, Client, Method, Request, Response, Uri};
+use hyper::{body, body::Buf, client, header, header::HeaderValue, Body, Client, Method, Request, Response, StatusCode, Uri};
 use hyper_rustls::HttpsConnector;
 use libflate::gzip;
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use percent_encoding::{percent_encode, CONTROLS};
 use serde_json::Value;
-use std::{io, result::Result, sync::atomic::Ordering::SeqCst};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::time::{Duration, Instant};
+use std::{io, result::Result};
 
 use crate::instance_info::INSTANCE_INFO;
+use crate::media_cache;
 use crate::server::RequestExt;
+use crate::utils::{record_upstream, SingleFlight};
 use crate::{config, dbg_msg};
 
 const REDDIT_URL_BASE: &str = "https://www.reddit.com";
 
+const DEFAULT_USER_AGENT: &str = concat!("web:libreddit:", env!("CARGO_PKG_VERSION"));
+
+/// User-Agent sent with requests to Reddit and its media servers. Set through
+/// `--user-agent`.
+pub static USER_AGENT: OnceCell<String> = OnceCell::new();
+
+fn user_agent() -> &'static str {
+	USER_AGENT.get().map_or(DEFAULT_USER_AGENT, String::as_str)
+}
+
+const DEFAULT_ACCEPT_LANGUAGE: &str = "en-US,en;q=0.5";
+
+tokio::task_local! {
+	/// Primary language tag of the visitor whose request is being handled,
+	/// from their `lang` setting or `Accept-Language`. Set by the server
+	/// around each route.
+	pub static LANGUAGE: String;
+
+	/// Address of the visitor whose request is being handled, for
+	/// `--log-upstream`. Set by the server around each route.
+	pub static CLIENT_IP: Option<IpAddr>;
+}
+
+// Reddit localizes some fields, so requests ask for the visitor's language
+fn language() -> String {
+	LANGUAGE.try_with(String::clone).unwrap_or_default()
+}
+
+/// Whether every request to Reddit is logged with its status and duration,
+/// set through `--log-upstream`. Off by default, as the URLs include what
+/// visitors search for.
+pub static LOG_UPSTREAM: AtomicBool = AtomicBool::new(false);
+
+// Logs a finished request to Reddit when `--log-upstream` is on, passing its
+// result through
+fn log_upstream<T>(method: &Method, url: &str, started: Instant, result: Result<Response<T>, hyper::Error>) -> Result<Response<T>, hyper::Error> {
+	if LOG_UPSTREAM.load(SeqCst) {
+		let outcome = result.as_ref().map_or_else(ToString::to_string, |response| response.status().to_string());
+		let client = CLIENT_IP.try_with(|ip| *ip).ok().flatten().map_or_else(|| "-".to_string(), |ip| ip.to_string());
+		eprintln!("{} {} - {} in {}ms for {}", method, url, outcome, started.elapsed().as_millis(), client);
+	}
+	result
+}
+
+/// Times a Reddit request failing with a transient error is retried, set
+/// through `--fetch-retries`.
+pub static FETCH_RETRIES: OnceCell<u32> = OnceCell::new();
+
+/// Wait before the first retry, doubling with every retry after it. Set
+/// through `--fetch-retry-delay`.
+pub static FETCH_RETRY_DELAY: OnceCell<Duration> = OnceCell::new();
+
+const DEFAULT_FETCH_RETRIES: u32 = 2;
+const DEFAULT_FETCH_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Most idle connections kept open to each of Reddit's hosts, set through
+/// `--pool-max-idle`. Defaults to a few per worker thread.
+pub static POOL_MAX_IDLE_PER_HOST: OnceCell<usize> = OnceCell::new();
+
+/// How long an idle connection is kept before it's closed, set through
+/// `--pool-idle-timeout`.
+pub static POOL_IDLE_TIMEOUT: OnceCell<Duration> = OnceCell::new();
+
+/// Idle connections kept per worker thread unless `--pool-max-idle` is given
+pub const POOL_IDLE_PER_WORKER: usize = 4;
+
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
 static CLIENT: Lazy<Client<HttpsConnector<HttpConnector>>> = Lazy::new(|| {
 	let https = hyper_rustls::HttpsConnectorBuilder::new().with_native_roots().https_only().enable_http1().build();
-	client::Client::builder().build(https)
+	let mut builder = client::Client::builder();
+	builder.pool_idle_timeout(POOL_IDLE_TIMEOUT.get().copied().unwrap_or(DEFAULT_POOL_IDLE_TIMEOUT));
+	if let Some(&max_idle) = POOL_MAX_IDLE_PER_HOST.get() {
+		builder.pool_max_idle_per_host(max_idle);
+	}
+	builder.build(https)
 });
 
 /// Gets the canonical path for a resource on Reddit. This is accomplished by
@@ -30,34 +109,45 @@ static CLIENT: Lazy<Client<HttpsConnector<HttpConnector>>> = Lazy::new(|| {
 /// `Location` header in the response, and the HTTP code is in the 3xx-family;
 /// the `String` will contain the path as reported in `Location`. The return
 /// value is `Ok(None)` if Reddit responded with a 3xx, but did not provide a
-/// `Location` header. An `Err(String)` is returned if Reddit responds with a
-/// 429, or if we were unable to decode the value in the `Location` header.
+/// `Location` header. An `Err(FetchError)` is returned if Reddit responds with
+/// a 429, or if we were unable to decode the value in the `Location` header.
 #[cached(size = 1024, time = 600, result = true)]
-pub async fn canonical_path(path: String) -> Result<Option<String>, String> {
+pub async fn canonical_path(path: String) -> Result<Option<String>, FetchError> {
 	let res = reddit_head(path.clone(), true).await?;
 	let status = res.status().as_u16();
 
 	match status {
-		429 => return Err("Too many requests.".to_string()),
+		429 => Err(FetchError::new(429, "Too many requests.")),
 
 		// If Reddit responds with a 2xx, then the path is already canonical.
-		200..=299 => return Ok(Some(path)),
+		200..=299 => Ok(Some(path)),
 
-		// If Reddit responds with anything other than 3xx (except for the 2xx as
-		// above), return a None.
-		300..=399 => return Ok(None),
-
-		_ => Ok(
+		// If Reddit responds with a 3xx, the canonical path is in the Location
+		// header.
+		300..=399 => Ok(
 			res
 				.headers()
 				.get(header::LOCATION)
 				.map(|val| percent_encode(val.as_bytes(), CONTROLS).to_string().trim_start_matches(REDDIT_URL_BASE).to_string()),
 		),
+
+		// If Reddit responds with anything other than 3xx (except for the 2xx as
+		// above), return a None.
+		_ => Ok(None),
 	}
 }
 
 pub async fn proxy(req: Request<Body>, format: &str) -> Result<Response<Body>, String> {
-	let mut url = format!("{}?{}", format, req.uri().query().unwrap_or_default());
+	// Our own parameters asking for a download aren't passed on to Reddit
+	let (download, query): (Vec<&str>, Vec<&str>) = req
+		.uri()
+		.query()
+		.unwrap_or_default()
+		.split('&')
+		.filter(|pair| !pair.is_empty())
+		.partition(|pair| pair.starts_with("download=") || pair.starts_with("filename="));
+
+	let mut url = format!("{}?{}", format, query.join("&"));
 
 	// For each parameter in request
 	for (name, value) in req.params().iter() {
@@ -65,7 +155,101 @@ pub async fn proxy(req: Request<Body>, format: &str) -> Result<Response<Body>, S
 		url = url.replace(&format!("{{{}}}", name), value);
 	}
 
-	stream(&url, &req).await
+	// Range requests get only part of a file, so they always go to Reddit
+	let cacheable = media_cache::enabled() && !req.headers().contains_key(header::RANGE);
+	let mut res = match cacheable.then(|| media_cache::get(&url)).flatten() {
+		Some(res) => res,
+		None if cacheable => cache_response(&url, stream(&url, &req).await?).await?,
+		None => stream(&url, &req).await?,
+	};
+
+	// Some of Reddit's media servers label images as plain binary data, which
+	// browsers won't show as images because of `X-Content-Type-Options: nosniff`
+	let labelled = res
+		.headers()
+		.get(header::CONTENT_TYPE)
+		.and_then(|value| value.to_str().ok())
+		.is_some_and(|value| !value.is_empty() && !value.ends_with("/octet-stream") && !value.starts_with("text/plain"));
+	if !labelled && res.status().is_success() {
+		if let Some(content_type) = extension(req.uri().path()).and_then(media_type) {
+			res.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+		}
+	}
+
+	// Emoji and subreddit styles (icons, banners, flair) get a new URL when
+	// they change, so browsers can keep them for a long time
+	if res.status().is_success() && (format.starts_with("https://emoji.redditmedia.com/") || format.starts_with("https://styles.redditmedia.com/")) {
+		res
+			.headers_mut()
+			.insert(header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=2592000, immutable"));
+	}
+
+	if download.contains(&"download=1") {
+		res.headers_mut().insert(header::CONTENT_DISPOSITION, attachment(req.uri().path(), &download));
+	}
+
+	Ok(res)
+}
+
+// Keeps a copy of media small enough to cache that Reddit allows to be
+// cached. Anything else is streamed through untouched.
+async fn cache_response(url: &str, res: Response<Body>) -> Result<Response<Body>, String> {
+	let small = res
+		.headers()
+		.get(header::CONTENT_LENGTH)
+		.and_then(|value| value.to_str().ok()?.parse::<usize>().ok())
+		.is_some_and(|length| length <= media_cache::max_entry());
+	let Some(ttl) = media_cache::ttl(res.headers()).filter(|_| small && res.status() == StatusCode::OK) else {
+		return Ok(res);
+	};
+
+	let (parts, body) = res.into_parts();
+	let bytes = body::to_bytes(body).await.map_err(|e| e.to_string())?;
+	media_cache::insert(url, parts.status, parts.headers.clone(), bytes.clone(), ttl);
+
+	Ok(Response::from_parts(parts, Body::from(bytes)))
+}
+
+/// `Content-Disposition` value saving a proxied file under the name in the
+/// `filename` parameter, keeping the extension of the proxied `path`.
+fn attachment(path: &str, params: &[&str]) -> HeaderValue {
+	let name: String = params
+		.iter()
+		.find_map(|pair| pair.strip_prefix("filename="))
+		.unwrap_or_default()
+		.chars()
+		.filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_')
+		.collect();
+
+	match (name.is_empty(), extension(path)) {
+		(true, _) => HeaderValue::from_static("attachment"),
+		(false, Some(ext)) => HeaderValue::from_str(&format!("attachment; filename=\"{}.{}\"", name, ext)).unwrap_or(HeaderValue::from_static("attachment")),
+		(false, None) => HeaderValue::from_str(&format!("attachment; filename=\"{}\"", name)).unwrap_or(HeaderValue::from_static("attachment")),
+	}
+}
+
+// Extension of the file at the end of `path`, if it looks like one
+fn extension(path: &str) -> Option<&str> {
+	path
+		.rsplit('/')
+		.next()
+		.and_then(|file| file.rsplit_once('.'))
+		.map(|(_, ext)| ext)
+		.filter(|ext| !ext.is_empty() && ext.len() <= 5 && ext.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+// Content type of the media formats Reddit serves, by file extension
+fn media_type(extension: &str) -> Option<&'static str> {
+	Some(match extension.to_ascii_lowercase().as_str() {
+		"png" => "image/png",
+		"jpg" | "jpeg" => "image/jpeg",
+		"gif" => "image/gif",
+		"webp" => "image/webp",
+		"svg" => "image/svg+xml",
+		"mp4" => "video/mp4",
+		"m3u8" => "application/vnd.apple.mpegurl",
+		_ => return None,
+	})
 }
 
 async fn stream(url: &str, req: &Request<Body>) -> Result<Response<Body>, String> {
@@ -75,7 +259,7 @@ async fn stream(url: &str, req: &Request<Body>) -> Result<Response<Body>, String
 	// Build the hyper client from the HTTPS connector.
 	let client: client::Client<_, hyper::Body> = CLIENT.clone();
 
-	let mut builder = Request::get(uri);
+	let mut builder = Request::get(uri).header("User-Agent", user_agent());
 
 	// Copy useful headers from original request
 	for &key in &["Range", "If-Modified-Since", "Cache-Control"] {
@@ -110,21 +294,83 @@ async fn stream(url: &str, req: &Request<Body>) -> Result<Response<Body>, String
 		.map_err(|e| e.to_string())
 }
 
+/// A failed request to Reddit, with the status to show the visitor.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FetchError {
+	pub status: u16,
+	pub msg: String,
+	/// Whether trying again may help: the connection to Reddit broke down or
+	/// Reddit had a server error, rather than refusing the request
+	pub transient: bool,
+}
+
+impl FetchError {
+	/// An error about the request or content itself, which will happen again
+	pub fn new(status: u16, msg: impl ToString) -> Self {
+		Self {
+			status,
+			msg: msg.to_string(),
+			transient: false,
+		}
+	}
+
+	/// A failure of Reddit or the connection to it, worth retrying
+	pub fn transient(status: u16, msg: impl ToString) -> Self {
+		Self {
+			transient: true,
+			..Self::new(status, msg)
+		}
+	}
+
+	// Adds what was being done when the error happened to its message
+	fn context(self, what: &str) -> Self {
+		Self {
+			msg: format!("{}: {}", what, self.msg),
+			..self
+		}
+	}
+}
+
+impl fmt::Display for FetchError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.msg)
+	}
+}
+
+impl From<FetchError> for String {
+	fn from(e: FetchError) -> Self {
+		e.msg
+	}
+}
+
+// Libreddit's own errors are about content it can't find or show
+impl From<String> for FetchError {
+	fn from(msg: String) -> Self {
+		Self::new(404, msg)
+	}
+}
+
+impl From<&str> for FetchError {
+	fn from(msg: &str) -> Self {
+		Self::new(404, msg)
+	}
+}
+
 /// Makes a GET request to Reddit at `path`. By default, this will honor HTTP
 /// 3xx codes Reddit returns and will automatically redirect.
-fn reddit_get(path: String, quarantine: bool) -> Boxed<Result<Response<Body>, String>> {
+fn reddit_get(path: String, quarantine: bool) -> Boxed<Result<Response<Body>, FetchError>> {
 	request(&Method::GET, path, true, quarantine)
 }
 
 /// Makes a HEAD request to Reddit at `path`. This will not follow redirects.
-fn reddit_head(path: String, quarantine: bool) -> Boxed<Result<Response<Body>, String>> {
+fn reddit_head(path: String, quarantine: bool) -> Boxed<Result<Response<Body>, FetchError>> {
 	request(&Method::HEAD, path, false, quarantine)
 }
 
 /// Makes a request to Reddit. If `redirect` is `true`, request_with_redirect
 /// will recurse on the URL that Reddit provides in the Location HTTP header
 /// in its response.
-fn request(method: &'static Method, path: String, redirect: bool, quarantine: bool) -> Boxed<Result<Response<Body>, String>> {
+fn request(method: &'static Method, path: String, redirect: bool, quarantine: bool) -> Boxed<Result<Response<Body>, FetchError>> {
 	// Increment reddit request count. This will include head requests.
 	if config::get_setting("LIBREDDIT_DISABLE_STATS_COLLECTION").is_none() {
 		INSTANCE_INFO.reddit_requests.fetch_add(1, SeqCst);
@@ -140,16 +386,22 @@ fn request(method: &'static Method, path: String, redirect: bool, quarantine: bo
 	let builder = Request::builder()
 		.method(method)
 		.uri(&url)
-		.header("User-Agent", concat!("web:libreddit:", env!("CARGO_PKG_VERSION")))
+		.header("User-Agent", user_agent())
 		.header("Host", "www.reddit.com")
 		.header("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/webp,*/*;q=0.8")
 		.header("Accept-Encoding", if method == Method::GET { "gzip" } else { "identity" })
-		.header("Accept-Language", "en-US,en;q=0.5")
+		.header(
+			"Accept-Language",
+			match language().as_str() {
+				"" => DEFAULT_ACCEPT_LANGUAGE.to_string(),
+				language => format!("{},{}", language, DEFAULT_ACCEPT_LANGUAGE),
+			},
+		)
 		.header("Connection", "keep-alive")
 		.header(
 			"Cookie",
 			if quarantine {
-				"_options=%7B%22pref_quarantine_optin%22%3A%20true%2C%20%22pref_gated_sr_optin%22%3A%20true%7D"
+				"_options=%7B%22pref_quarantine_optin%22%3A%20true%2C%20%22pref_gated_sr_optin%22%3A%20true%7D; over18=1"
 			} else {
 				""
 			},
@@ -157,8 +409,9 @@ fn request(method: &'static Method, path: String, redirect: bool, quarantine: bo
 		.body(Body::empty());
 
 	async move {
+		let started = Instant::now();
 		match builder {
-			Ok(req) => match client.request(req).await {
+			Ok(req) => match log_upstream(method, &url, started, client.request(req).await) {
 				Ok(mut response) => {
 					// Reddit may respond with a 3xx. Decide whether or not to
 					// redirect based on caller params.
@@ -167,6 +420,17 @@ fn request(method: &'static Method, path: String, redirect: bool, quarantine: bo
 							return Ok(response);
 						};
 
+						// Reddit sends anonymous visitors of some NSFW content to an
+						// over-18 confirmation page instead of the JSON
+						if response
+							.headers()
+							.get(header::LOCATION)
+							.and_then(|val| val.to_str().ok())
+							.is_some_and(|location| location.contains("/over18?"))
+						{
+							return Err(FetchError::new(403, "over18"));
+						}
+
 						return request(
 							method,
 							response
@@ -205,9 +469,9 @@ fn request(method: &'static Method, path: String, redirect: bool, quarantine: bo
 								Ok(val) => match val {
 									"gzip" => {}
 									"identity" => return Ok(response),
-									_ => return Err("Reddit response was encoded with an unsupported compressor".to_string()),
+									_ => return Err(FetchError::new(502, "Reddit response was encoded with an unsupported compressor")),
 								},
-								Err(_) => return Err("Reddit response was invalid".to_string()),
+								Err(_) => return Err(FetchError::new(502, "Reddit response was invalid")),
 							}
 
 							// We get here if the body is gzip-compressed.
@@ -220,17 +484,17 @@ fn request(method: &'static Method, path: String, redirect: bool, quarantine: bo
 							{
 								let mut aggregated_body = match body::aggregate(response.body_mut()).await {
 									Ok(b) => b.reader(),
-									Err(e) => return Err(e.to_string()),
+									Err(e) => return Err(FetchError::transient(502, e)),
 								};
 
 								let mut decoder = match gzip::Decoder::new(&mut aggregated_body) {
 									Ok(decoder) => decoder,
-									Err(e) => return Err(e.to_string()),
+									Err(e) => return Err(FetchError::new(502, e)),
 								};
 
 								decompressed = Vec::<u8>::new();
 								if let Err(e) = io::copy(&mut decoder, &mut decompressed) {
-									return Err(e.to_string());
+									return Err(FetchError::new(502, e));
 								};
 							}
 
@@ -245,24 +509,82 @@ fn request(method: &'static Method, path: String, redirect: bool, quarantine: bo
 				Err(e) => {
 					dbg_msg!("{} {}: {}", method, path, e);
 
-					Err(e.to_string())
+					Err(FetchError::transient(if e.is_timeout() { 504 } else { 502 }, e))
 				}
 			},
-			Err(_) => Err("Post url contains non-ASCII characters".to_string()),
+			Err(_) => Err(FetchError::new(400, "Post url contains non-ASCII characters")),
 		}
 	}
 	.boxed()
 }
 
+/// How often `--check-upstream` retries while Reddit can't be reached
+pub const UPSTREAM_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Fetches a listing until Reddit answers, so operators find out about
+/// network or firewall problems at deploy time instead of from users.
+/// Pages show a "starting up" error until then, see `record_upstream`.
+pub async fn check_upstream() {
+	loop {
+		match json("/r/popular/hot.json?limit=1&raw_json=1".to_string(), false).await {
+			Err(e) if e.status >= 500 => {
+				eprintln!(
+					"Warning: can't reach Reddit ({}), retrying in {}s. Check this host's network and firewall.",
+					e,
+					UPSTREAM_CHECK_INTERVAL.as_secs()
+				);
+				tokio::time::sleep(UPSTREAM_CHECK_INTERVAL).await;
+			}
+			_ => break,
+		}
+	}
+
+	println!("Reddit is reachable.");
+}
+
+/// Identical requests to Reddit that are in flight at the same time.
+static JSON_FLIGHTS: Lazy<SingleFlight<Result<Value, FetchError>>> = Lazy::new(SingleFlight::new);
+
 // Make a request to a Reddit API and parse the JSON response
+pub async fn json(path: String, quarantine: bool) -> Result<Value, FetchError> {
+	localized_json(path, quarantine, language()).await
+}
+
+// Responses can differ by language, so it's part of the cache key. The
+// request itself picks the language up from `LANGUAGE`.
 #[cached(size = 100, time = 30, result = true)]
-pub async fn json(path: String, quarantine: bool) -> Result<Value, String> {
-	// Closure to quickly build errors
-	let err = |msg: &str, e: String| -> Result<Value, String> {
-		// eprintln!("{} - {}: {}", url, msg, e);
-		Err(format!("{}: {}", msg, e))
-	};
+async fn localized_json(path: String, quarantine: bool, language: String) -> Result<Value, FetchError> {
+	let key = format!("{}:{}:{}", quarantine, language, path);
+	JSON_FLIGHTS
+		.run(&key, || async {
+			let result = fetch_json_with_retries(path, quarantine).await;
+			record_upstream(&result);
+			result
+		})
+		.await
+}
+
+// Retries requests that failed because of Reddit or the connection to it, in
+// case it was just a hiccup. Errors about the content itself are final.
+async fn fetch_json_with_retries(path: String, quarantine: bool) -> Result<Value, FetchError> {
+	let retries = FETCH_RETRIES.get().copied().unwrap_or(DEFAULT_FETCH_RETRIES);
+	let mut delay = FETCH_RETRY_DELAY.get().copied().unwrap_or(DEFAULT_FETCH_RETRY_DELAY);
+
+	let mut attempt = 0;
+	loop {
+		let result = fetch_json(path.clone(), quarantine).await;
+		match &result {
+			Err(e) if attempt < retries && e.transient => {
+				tokio::time::sleep(delay).await;
+				delay *= 2;
+				attempt += 1;
+			}
+			_ => return result,
+		}
+	}
+}
 
+async fn fetch_json(path: String, quarantine: bool) -> Result<Value, FetchError> {
 	// Fetch the url...
 	match reddit_get(path.clone(), quarantine).await {
 		Ok(response) => {
@@ -270,40 +592,57 @@ pub async fn json(path: String, quarantine: bool) -> Result<Value, String> {
 
 			// asynchronously aggregate the chunks of the body
 			match hyper::body::aggregate(response).await {
-				Ok(body) => {
-					// Parse the response from Reddit as JSON
-					match serde_json::from_reader(body.reader()) {
-						Ok(value) => {
-							let json: Value = value;
-							// If Reddit returned an error
-							if json["error"].is_i64() {
-								Err(
-									json["reason"]
-										.as_str()
-										.unwrap_or_else(|| {
-											json["message"].as_str().unwrap_or_else(|| {
-												eprintln!("{}{} - Error parsing reddit error", REDDIT_URL_BASE, path);
-												"Error parsing reddit error"
-											})
-										})
-										.to_string(),
-								)
-							} else {
-								Ok(json)
-							}
-						}
-						Err(e) => {
-							if status.is_server_error() {
-								Err("Reddit is having issues, check if there's an outage".to_string())
-							} else {
-								err("Failed to parse page JSON data", e.to_string())
-							}
-						}
-					}
-				}
-				Err(e) => err("Failed receiving body from Reddit", e.to_string()),
+				Ok(body) => parse_json(&path, status, body.reader()),
+				Err(e) => Err(FetchError::transient(502, e).context("Failed receiving body from Reddit")),
 			}
 		}
-		Err(e) => err("Couldn't send request to Reddit", e),
+		// The over-18 gate is handled by the pages, so pass it through as is
+		Err(e) if e.msg == "over18" => Err(e),
+		Err(e) => Err(e.context("Couldn't send request to Reddit")),
+	}
+}
+
+// Parses a response from Reddit's JSON API with the status it came with.
+// Reddit reports errors as JSON with their status as `error`, and answers
+// with an HTML page when it's having issues.
+fn parse_json(path: &str, status: StatusCode, body: impl io::Read) -> Result<Value, FetchError> {
+	match serde_json::from_reader::<_, Value>(body) {
+		// If Reddit returned an error
+		Ok(json) if json["error"].is_i64() => {
+			let status = json["error"].as_u64().and_then(|code| u16::try_from(code).ok()).unwrap_or(status.as_u16());
+			let msg = json["reason"].as_str().or_else(|| json["message"].as_str()).unwrap_or_else(|| {
+				eprintln!("{}{} - Error parsing reddit error", REDDIT_URL_BASE, path);
+				"Error parsing reddit error"
+			});
+			Err(if status >= 500 { FetchError::transient(502, msg) } else { FetchError::new(status, msg) })
+		}
+		Ok(json) => Ok(json),
+		Err(_) if status.is_server_error() => Err(FetchError::transient(502, "Reddit is having issues, check if there's an outage")),
+		Err(_) if status == StatusCode::TOO_MANY_REQUESTS => Err(FetchError::new(429, "Too many requests.")),
+		Err(e) => Err(FetchError::new(502, e).context("Failed to parse page JSON data")),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{parse_json, FetchError};
+	use hyper::StatusCode;
+
+	#[test]
+	fn test_parse_json() {
+		let parse = |status: u16, body: &str| parse_json("/r/rust.json", StatusCode::from_u16(status).unwrap(), body.as_bytes());
+
+		assert!(parse(200, r#"{"kind": "Listing"}"#).is_ok());
+		assert_eq!(parse(403, r#"{"reason": "private", "error": 403}"#), Err(FetchError::new(403, "private")));
+		assert_eq!(
+			parse(200, r#"{"message": "Too Many Requests", "error": 429}"#),
+			Err(FetchError::new(429, "Too Many Requests"))
+		);
+		assert_eq!(parse(429, "<html>").map_err(|e| (e.status, e.transient)), Err((429, false)));
+
+		// Reddit's outage pages are worth retrying, garbage from a working Reddit isn't
+		assert_eq!(parse(503, "<html>").map_err(|e| (e.status, e.transient)), Err((502, true)));
+		assert_eq!(parse(500, r#"{"message": "Internal Server Error", "error": 500}"#).map_err(|e| e.transient), Err(true));
+		assert_eq!(parse(200, "<html>").map_err(|e| (e.status, e.transient)), Err((502, false)));
 	}
 }
@@ -53,6 +53,9 @@ pub struct Config {
 	#[serde(rename = "LIBREDDIT_DEFAULT_HIDE_HLS_NOTIFICATION")]
 	pub(crate) default_hide_hls_notification: Option<String>,
 
+	#[serde(rename = "LIBREDDIT_DEFAULT_AUTOPLAY_VIDEOS")]
+	pub(crate) default_autoplay_videos: Option<String>,
+
 	#[serde(rename = "LIBREDDIT_DEFAULT_HIDE_AWARDS")]
 	pub(crate) default_hide_awards: Option<String>,
 
@@ -62,6 +65,87 @@ pub struct Config {
 	#[serde(rename = "LIBREDDIT_DEFAULT_DISABLE_VISIT_REDDIT_CONFIRMATION")]
 	pub(crate) default_disable_visit_reddit_confirmation: Option<String>,
 
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_CONTROVERSIAL")]
+	pub(crate) default_show_controversial: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_DATA_SAVER")]
+	pub(crate) default_data_saver: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_FULL_RES_IMAGES")]
+	pub(crate) default_full_res_images: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_COMMENT_LIMIT")]
+	pub(crate) default_comment_limit: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_POST_FLAIR")]
+	pub(crate) default_show_post_flair: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_USER_FLAIR")]
+	pub(crate) default_show_user_flair: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_ANIMATE_THUMBNAILS")]
+	pub(crate) default_animate_thumbnails: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_PREVIEW_QUALITY")]
+	pub(crate) default_preview_quality: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_VOTE_BREAKDOWN")]
+	pub(crate) default_show_vote_breakdown: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_UPVOTE_RATIO")]
+	pub(crate) default_show_upvote_ratio: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_VOTE_BUTTONS")]
+	pub(crate) default_show_vote_buttons: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_SUBSCRIBER_COUNTS")]
+	pub(crate) default_show_subscriber_counts: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_RENDER_MATH")]
+	pub(crate) default_render_math: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_INLINE_THREADS")]
+	pub(crate) default_inline_threads: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SUBREDDIT_NAMES")]
+	pub(crate) default_subreddit_names: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_LINK_SUBREDDIT_NAMES")]
+	pub(crate) default_link_subreddit_names: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_DOMAINS")]
+	pub(crate) default_show_domains: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_EXTERNAL_LINKS_NEW_TAB")]
+	pub(crate) default_external_links_new_tab: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_HIDE_SEEN_POSTS")]
+	pub(crate) default_hide_seen_posts: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_HIDE_COMMENT_SCORES")]
+	pub(crate) default_hide_comment_scores: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_STRIP_TRACKING_PARAMS")]
+	pub(crate) default_strip_tracking_params: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SCORE_FORMAT")]
+	pub(crate) default_score_format: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_AUTHOR")]
+	pub(crate) default_show_author: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_UI_DENSITY")]
+	pub(crate) default_ui_density: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_COLLAPSE_LONG_QUOTES")]
+	pub(crate) default_collapse_long_quotes: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_SHOW_READING_TIME")]
+	pub(crate) default_show_reading_time: Option<String>,
+
+	#[serde(rename = "LIBREDDIT_DEFAULT_LANG")]
+	pub(crate) default_lang: Option<String>,
+
 	#[serde(rename = "LIBREDDIT_BANNER")]
 	pub(crate) banner: Option<String>,
 
@@ -100,9 +184,37 @@ impl Config {
 			default_blur_nsfw: parse("LIBREDDIT_DEFAULT_BLUR_NSFW"),
 			default_use_hls: parse("LIBREDDIT_DEFAULT_USE_HLS"),
 			default_hide_hls_notification: parse("LIBREDDIT_DEFAULT_HIDE_HLS"),
+			default_autoplay_videos: parse("LIBREDDIT_DEFAULT_AUTOPLAY_VIDEOS"),
 			default_hide_awards: parse("LIBREDDIT_DEFAULT_HIDE_AWARDS"),
 			default_subscriptions: parse("LIBREDDIT_DEFAULT_SUBSCRIPTIONS"),
 			default_disable_visit_reddit_confirmation: parse("LIBREDDIT_DEFAULT_DISABLE_VISIT_REDDIT_CONFIRMATION"),
+			default_show_controversial: parse("LIBREDDIT_DEFAULT_SHOW_CONTROVERSIAL"),
+			default_data_saver: parse("LIBREDDIT_DEFAULT_DATA_SAVER"),
+			default_full_res_images: parse("LIBREDDIT_DEFAULT_FULL_RES_IMAGES"),
+			default_comment_limit: parse("LIBREDDIT_DEFAULT_COMMENT_LIMIT"),
+			default_show_post_flair: parse("LIBREDDIT_DEFAULT_SHOW_POST_FLAIR"),
+			default_show_user_flair: parse("LIBREDDIT_DEFAULT_SHOW_USER_FLAIR"),
+			default_animate_thumbnails: parse("LIBREDDIT_DEFAULT_ANIMATE_THUMBNAILS"),
+			default_preview_quality: parse("LIBREDDIT_DEFAULT_PREVIEW_QUALITY"),
+			default_show_vote_breakdown: parse("LIBREDDIT_DEFAULT_SHOW_VOTE_BREAKDOWN"),
+			default_show_upvote_ratio: parse("LIBREDDIT_DEFAULT_SHOW_UPVOTE_RATIO"),
+			default_show_vote_buttons: parse("LIBREDDIT_DEFAULT_SHOW_VOTE_BUTTONS"),
+			default_show_subscriber_counts: parse("LIBREDDIT_DEFAULT_SHOW_SUBSCRIBER_COUNTS"),
+			default_render_math: parse("LIBREDDIT_DEFAULT_RENDER_MATH"),
+			default_inline_threads: parse("LIBREDDIT_DEFAULT_INLINE_THREADS"),
+			default_subreddit_names: parse("LIBREDDIT_DEFAULT_SUBREDDIT_NAMES"),
+			default_link_subreddit_names: parse("LIBREDDIT_DEFAULT_LINK_SUBREDDIT_NAMES"),
+			default_show_domains: parse("LIBREDDIT_DEFAULT_SHOW_DOMAINS"),
+			default_external_links_new_tab: parse("LIBREDDIT_DEFAULT_EXTERNAL_LINKS_NEW_TAB"),
+			default_hide_seen_posts: parse("LIBREDDIT_DEFAULT_HIDE_SEEN_POSTS"),
+			default_hide_comment_scores: parse("LIBREDDIT_DEFAULT_HIDE_COMMENT_SCORES"),
+			default_strip_tracking_params: parse("LIBREDDIT_DEFAULT_STRIP_TRACKING_PARAMS"),
+			default_score_format: parse("LIBREDDIT_DEFAULT_SCORE_FORMAT"),
+			default_show_author: parse("LIBREDDIT_DEFAULT_SHOW_AUTHOR"),
+			default_ui_density: parse("LIBREDDIT_DEFAULT_UI_DENSITY"),
+			default_collapse_long_quotes: parse("LIBREDDIT_DEFAULT_COLLAPSE_LONG_QUOTES"),
+			default_show_reading_time: parse("LIBREDDIT_DEFAULT_SHOW_READING_TIME"),
+			default_lang: parse("LIBREDDIT_DEFAULT_LANG"),
 			banner: parse("LIBREDDIT_BANNER"),
 			robots_disable_indexing: parse("LIBREDDIT_ROBOTS_DISABLE_INDEXING"),
 			disable_stats_collection: parse("LIBREDDIT_DISABLE_STATS_COLLECTION"),
@@ -124,9 +236,37 @@ fn get_setting_from_config(name: &str, config: &Config) -> Option<String> {
 		"LIBREDDIT_DEFAULT_USE_HLS" => config.default_use_hls.clone(),
 		"LIBREDDIT_DEFAULT_HIDE_HLS_NOTIFICATION" => config.default_hide_hls_notification.clone(),
 		"LIBREDDIT_DEFAULT_WIDE" => config.default_wide.clone(),
+		"LIBREDDIT_DEFAULT_AUTOPLAY_VIDEOS" => config.default_autoplay_videos.clone(),
 		"LIBREDDIT_DEFAULT_HIDE_AWARDS" => config.default_hide_awards.clone(),
 		"LIBREDDIT_DEFAULT_SUBSCRIPTIONS" => config.default_subscriptions.clone(),
 		"LIBREDDIT_DEFAULT_DISABLE_VISIT_REDDIT_CONFIRMATION" => config.default_disable_visit_reddit_confirmation.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_CONTROVERSIAL" => config.default_show_controversial.clone(),
+		"LIBREDDIT_DEFAULT_DATA_SAVER" => config.default_data_saver.clone(),
+		"LIBREDDIT_DEFAULT_FULL_RES_IMAGES" => config.default_full_res_images.clone(),
+		"LIBREDDIT_DEFAULT_COMMENT_LIMIT" => config.default_comment_limit.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_POST_FLAIR" => config.default_show_post_flair.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_USER_FLAIR" => config.default_show_user_flair.clone(),
+		"LIBREDDIT_DEFAULT_ANIMATE_THUMBNAILS" => config.default_animate_thumbnails.clone(),
+		"LIBREDDIT_DEFAULT_PREVIEW_QUALITY" => config.default_preview_quality.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_VOTE_BREAKDOWN" => config.default_show_vote_breakdown.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_UPVOTE_RATIO" => config.default_show_upvote_ratio.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_VOTE_BUTTONS" => config.default_show_vote_buttons.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_SUBSCRIBER_COUNTS" => config.default_show_subscriber_counts.clone(),
+		"LIBREDDIT_DEFAULT_RENDER_MATH" => config.default_render_math.clone(),
+		"LIBREDDIT_DEFAULT_INLINE_THREADS" => config.default_inline_threads.clone(),
+		"LIBREDDIT_DEFAULT_SUBREDDIT_NAMES" => config.default_subreddit_names.clone(),
+		"LIBREDDIT_DEFAULT_LINK_SUBREDDIT_NAMES" => config.default_link_subreddit_names.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_DOMAINS" => config.default_show_domains.clone(),
+		"LIBREDDIT_DEFAULT_EXTERNAL_LINKS_NEW_TAB" => config.default_external_links_new_tab.clone(),
+		"LIBREDDIT_DEFAULT_HIDE_SEEN_POSTS" => config.default_hide_seen_posts.clone(),
+		"LIBREDDIT_DEFAULT_HIDE_COMMENT_SCORES" => config.default_hide_comment_scores.clone(),
+		"LIBREDDIT_DEFAULT_STRIP_TRACKING_PARAMS" => config.default_strip_tracking_params.clone(),
+		"LIBREDDIT_DEFAULT_SCORE_FORMAT" => config.default_score_format.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_AUTHOR" => config.default_show_author.clone(),
+		"LIBREDDIT_DEFAULT_UI_DENSITY" => config.default_ui_density.clone(),
+		"LIBREDDIT_DEFAULT_COLLAPSE_LONG_QUOTES" => config.default_collapse_long_quotes.clone(),
+		"LIBREDDIT_DEFAULT_SHOW_READING_TIME" => config.default_show_reading_time.clone(),
+		"LIBREDDIT_DEFAULT_LANG" => config.default_lang.clone(),
 		"LIBREDDIT_BANNER" => config.banner.clone(),
 		"LIBREDDIT_ROBOTS_DISABLE_INDEXING" => config.robots_disable_indexing.clone(),
 		"LIBREDDIT_DISABLE_STATS_COLLECTION" => config.disable_stats_collection.clone(),
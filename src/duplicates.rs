@@ -3,7 +3,7 @@
 use crate::client::json;
 use crate::server::RequestExt;
 use crate::subreddit::{can_access_quarantine, quarantine};
-use crate::utils::{error, filter_posts, get_filters, nsfw_landing, parse_post, template, Post, Preferences};
+use crate::utils::{cache, error, filter_posts, get_filters, nsfw_landing, parse_post, template, CachePolicy, Post, Preferences};
 
 use askama::Template;
 use hyper::{Body, Request, Response};
@@ -197,24 +197,27 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 				}
 			}
 
-			template(DuplicatesTemplate {
-				params: DuplicatesParams { before, after, sort },
-				post,
-				duplicates,
-				prefs: Preferences::new(&req),
-				url: req_url,
-				num_posts_filtered,
-				all_posts_filtered,
-			})
+			cache(
+				template(DuplicatesTemplate {
+					params: DuplicatesParams { before, after, sort },
+					post,
+					duplicates,
+					prefs: Preferences::new(&req),
+					url: req_url,
+					num_posts_filtered,
+					all_posts_filtered,
+				}),
+				CachePolicy::Post,
+			)
 		}
 
 		// Process error.
-		Err(msg) => {
-			if msg == "quarantined" || msg == "gated" {
+		Err(e) => {
+			if e.msg == "quarantined" || e.msg == "gated" {
 				let sub = req.param("sub").unwrap_or_default();
-				quarantine(req, sub, msg)
+				quarantine(req, sub, e.msg)
 			} else {
-				error(req, msg).await
+				error(req, e).await
 			}
 		}
 	}
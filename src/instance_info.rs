@@ -3,7 +3,7 @@ use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
 use crate::{
 	config::{Config, CONFIG},
 	server::RequestExt,
-	utils::{ErrorTemplate, Preferences},
+	utils::{rebase_html, ErrorTemplate, Preferences, HTML_CONTENT_TYPE},
 };
 use askama::Template;
 use build_html::{Container, Html, HtmlContainer, Table};
@@ -21,21 +21,26 @@ pub(crate) static INSTANCE_INFO: Lazy<InstanceInfo> = Lazy::new(InstanceInfo::ne
 pub async fn instance_info(req: Request<Body>) -> Result<Response<Body>, String> {
 	// This will retrieve the extension given, or create a new string - which will
 	// simply become the last option, an HTML page.
-	let extension = req.param("extension").unwrap_or(String::new());
+	let extension = req.param("extension").unwrap_or_default();
 	let response = match extension.as_str() {
 		"yaml" | "yml" => info_yaml(),
 		"txt" => info_txt(),
 		"json" => info_json(),
 		"html" | "" => info_html(req),
 		_ => {
-			let error = ErrorTemplate {
-				msg: "Error: Invalid info extension".into(),
-				prefs: Preferences::new(&req),
-				url: req.uri().to_string(),
-			}
-			.render()
-			.unwrap();
-			Response::builder().status(404).header("content-type", "text/html; charset=utf-8").body(error.into())
+			let error = rebase_html(
+				ErrorTemplate {
+					msg: "Error: Invalid info extension".into(),
+					status: 404,
+					back: "/info".to_string(),
+					suggestions: Vec::new(),
+					prefs: Preferences::new(&req),
+					url: req.uri().to_string(),
+				}
+				.render()
+				.unwrap(),
+			);
+			Response::builder().status(404).header("content-type", HTML_CONTENT_TYPE).body(error.into())
 		}
 	};
 	response.map_err(|err| format!("{err}"))
@@ -73,15 +78,17 @@ fn info_txt() -> Result<Response<Body>, Error> {
 		.body(Body::from(INSTANCE_INFO.to_string(StringType::Raw)))
 }
 fn info_html(req: Request<Body>) -> Result<Response<Body>, Error> {
-	let message = MessageTemplate {
-		title: String::from("Instance information"),
-		body: INSTANCE_INFO.to_string(StringType::Html),
-		prefs: Preferences::new(&req),
-		url: req.uri().to_string(),
-	}
-	.render()
-	.unwrap();
-	Response::builder().status(200).header("content-type", "text/html; charset=utf8").body(Body::from(message))
+	let message = rebase_html(
+		MessageTemplate {
+			title: String::from("Instance information"),
+			body: INSTANCE_INFO.to_string(StringType::Html),
+			prefs: Preferences::new(&req),
+			url: req.uri().to_string(),
+		}
+		.render()
+		.unwrap(),
+	);
+	Response::builder().status(200).header("content-type", HTML_CONTENT_TYPE).body(Body::from(message))
 }
 #[derive(Serialize, Deserialize, Default)]
 pub(crate) struct InstanceInfo {
@@ -150,6 +157,34 @@ impl InstanceInfo {
 				["Blur NSFW", &convert(&self.config.default_blur_nsfw)],
 				["Use HLS", &convert(&self.config.default_use_hls)],
 				["Hide HLS notification", &convert(&self.config.default_hide_hls_notification)],
+				["Autoplay videos", &convert(&self.config.default_autoplay_videos)],
+				["Mark controversial comments", &convert(&self.config.default_show_controversial)],
+				["Data saver", &convert(&self.config.default_data_saver)],
+				["Full resolution images", &convert(&self.config.default_full_res_images)],
+				["Comment limit", &convert(&self.config.default_comment_limit)],
+				["Show post flair", &convert(&self.config.default_show_post_flair)],
+				["Show user flair", &convert(&self.config.default_show_user_flair)],
+				["Animate GIF previews", &convert(&self.config.default_animate_thumbnails)],
+				["Image preview quality", &convert(&self.config.default_preview_quality)],
+				["Show vote breakdown on comment scores", &convert(&self.config.default_show_vote_breakdown)],
+				["Show upvote ratio", &convert(&self.config.default_show_upvote_ratio)],
+				["Show vote arrows", &convert(&self.config.default_show_vote_buttons)],
+				["Show subreddit member counts in listings", &convert(&self.config.default_show_subscriber_counts)],
+				["Render LaTeX math in posts and comments", &convert(&self.config.default_render_math)],
+				["Expand deep threads inline", &convert(&self.config.default_inline_threads)],
+				["Subreddit names", &convert(&self.config.default_subreddit_names)],
+				["Link subreddit names", &convert(&self.config.default_link_subreddit_names)],
+				["Show the domain of link posts", &convert(&self.config.default_show_domains)],
+				["Open external links in a new tab", &convert(&self.config.default_external_links_new_tab)],
+				["Hide posts marked as seen", &convert(&self.config.default_hide_seen_posts)],
+				["Hide comment scores", &convert(&self.config.default_hide_comment_scores)],
+				["Remove tracking parameters from links", &convert(&self.config.default_strip_tracking_params)],
+				["Score format", &convert(&self.config.default_score_format)],
+				["Show post authors in listings", &convert(&self.config.default_show_author)],
+				["Density", &convert(&self.config.default_ui_density)],
+				["Collapse long quotes in comments", &convert(&self.config.default_collapse_long_quotes)],
+				["Show the reading time of long text posts", &convert(&self.config.default_show_reading_time)],
+				["Language", &convert(&self.config.default_lang)],
 				["Subscriptions", &convert(&self.config.default_subscriptions)],
 			])
 			.with_header_row(["Default preferences"]),
@@ -183,6 +218,34 @@ impl InstanceInfo {
                     Default blur NSFW: {:?}\n
                     Default use HLS: {:?}\n
                     Default hide HLS notification: {:?}\n
+                    Default autoplay videos: {:?}\n
+                    Default mark controversial comments: {:?}\n
+                    Default data saver: {:?}\n
+                    Default full resolution images: {:?}\n
+                    Default comment limit: {:?}\n
+                    Default show post flair: {:?}\n
+                    Default show user flair: {:?}\n
+                    Default animate gif previews: {:?}\n
+                    Default image preview quality: {:?}\n
+                    Default show vote breakdown on comment scores: {:?}\n
+                    Default show upvote ratio: {:?}\n
+                    Default show vote arrows: {:?}\n
+                    Default show subreddit member counts in listings: {:?}\n
+                    Default render latex math in posts and comments: {:?}\n
+                    Default expand deep threads inline: {:?}\n
+                    Default subreddit names: {:?}\n
+                    Default link subreddit names: {:?}\n
+                    Default show the domain of link posts: {:?}\n
+                    Default open external links in a new tab: {:?}\n
+                    Default hide posts marked as seen: {:?}\n
+                    Default hide comment scores: {:?}\n
+                    Default remove tracking parameters from links: {:?}\n
+                    Default score format: {:?}\n
+                    Default show post authors in listings: {:?}\n
+                    Default density: {:?}\n
+                    Default collapse long quotes in comments: {:?}\n
+                    Default show the reading time of long text posts: {:?}\n
+                    Default language: {:?}\n
                     Default subscriptions: {:?}\n",
 					self.crate_version,
 					self.git_commit,
@@ -206,6 +269,34 @@ impl InstanceInfo {
 					self.config.default_blur_nsfw,
 					self.config.default_use_hls,
 					self.config.default_hide_hls_notification,
+					self.config.default_autoplay_videos,
+					self.config.default_show_controversial,
+					self.config.default_data_saver,
+					self.config.default_full_res_images,
+					self.config.default_comment_limit,
+					self.config.default_show_post_flair,
+					self.config.default_show_user_flair,
+					self.config.default_animate_thumbnails,
+					self.config.default_preview_quality,
+					self.config.default_show_vote_breakdown,
+					self.config.default_show_upvote_ratio,
+					self.config.default_show_vote_buttons,
+					self.config.default_show_subscriber_counts,
+					self.config.default_render_math,
+					self.config.default_inline_threads,
+					self.config.default_subreddit_names,
+					self.config.default_link_subreddit_names,
+					self.config.default_show_domains,
+					self.config.default_external_links_new_tab,
+					self.config.default_hide_seen_posts,
+					self.config.default_hide_comment_scores,
+					self.config.default_strip_tracking_params,
+					self.config.default_score_format,
+					self.config.default_show_author,
+					self.config.default_ui_density,
+					self.config.default_collapse_long_quotes,
+					self.config.default_show_reading_time,
+					self.config.default_lang,
 					self.config.default_subscriptions,
 				)
 			}
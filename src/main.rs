@@ -6,7 +6,11 @@
 mod config;
 mod duplicates;
 mod instance_info;
+mod math;
+mod media_cache;
 mod post;
+mod reader;
+mod saved;
 mod search;
 mod settings;
 mod subreddit;
@@ -14,71 +18,116 @@ mod user;
 mod utils;
 
 // Import Crates
-use clap::{Arg, ArgAction, Command};
+use clap::{parser::ValueSource, Arg, ArgAction, ArgMatches, Command};
 
 use futures_lite::FutureExt;
-use hyper::{header::HeaderValue, Body, Request, Response};
+use hyper::{header, header::HeaderValue, Body, Request, Response};
 
 mod client;
+use cached::proc_macro::cached;
 use client::{canonical_path, proxy};
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
 use server::RequestExt;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
 use utils::{error, redirect, ThemeAssets};
 
 mod server;
 
 // Create Services
 
-// Required for the manifest to be valid
-async fn pwa_logo() -> Result<Response<Body>, String> {
-	Ok(
-		Response::builder()
-			.status(200)
-			.header("content-type", "image/png")
-			.body(include_bytes!("../static/logo.png").as_ref().into())
-			.unwrap_or_default(),
-	)
+// Directory to read static assets from instead of the embedded copies. Set
+// through `--static-dir`.
+static STATIC_DIR: OnceCell<PathBuf> = OnceCell::new();
+
+/// Returns the contents of the static asset `name` and its ETag. If a static
+/// directory was given and contains the file, the copy on disk is used,
+/// otherwise the embedded bytes are returned.
+fn asset(name: &str, embedded: &[u8]) -> (Vec<u8>, String) {
+	STATIC_DIR
+		.get()
+		.and_then(|dir| read_asset(dir.join(name)))
+		.unwrap_or_else(|| (embedded.to_vec(), embedded_etag(name, embedded)))
 }
 
-// Required for iOS App Icons
-async fn iphone_logo() -> Result<Response<Body>, String> {
-	Ok(
-		Response::builder()
-			.status(200)
-			.header("content-type", "image/png")
-			.body(include_bytes!("../static/apple-touch-icon.png").as_ref().into())
-			.unwrap_or_default(),
-	)
+// Cache reads for a minute so that edits show up quickly without hitting the
+// disk on every request.
+#[cached(size = 64, time = 60)]
+fn read_asset(path: PathBuf) -> Option<(Vec<u8>, String)> {
+	std::fs::read(path).ok().map(|content| {
+		let tag = etag(&content);
+		(content, tag)
+	})
 }
 
-async fn favicon() -> Result<Response<Body>, String> {
-	Ok(
-		Response::builder()
-			.status(200)
-			.header("content-type", "image/vnd.microsoft.icon")
-			.header("Cache-Control", "public, max-age=1209600, s-maxage=86400")
-			.body(include_bytes!("../static/favicon.ico").as_ref().into())
-			.unwrap_or_default(),
-	)
+// Embedded assets never change, so their ETags are computed once, keyed by
+// the asset name.
+#[cached(key = "String", convert = r#"{ _name.to_string() }"#)]
+fn embedded_etag(_name: &str, content: &[u8]) -> String {
+	etag(content)
 }
 
-async fn font() -> Result<Response<Body>, String> {
-	Ok(
-		Response::builder()
-			.status(200)
-			.header("content-type", "font/woff2")
-			.header("Cache-Control", "public, max-age=1209600, s-maxage=86400")
-			.body(include_bytes!("../static/Inter.var.woff2").as_ref().into())
-			.unwrap_or_default(),
-	)
+/// Computes an ETag from the hash of `content`.
+fn etag(content: &[u8]) -> String {
+	let mut hasher = DefaultHasher::new();
+	content.hash(&mut hasher);
+	format!("\"{:016x}\"", hasher.finish())
 }
 
-async fn resource(body: &str, content_type: &str, cache: bool) -> Result<Response<Body>, String> {
-	let mut res = Response::builder()
-		.status(200)
-		.header("content-type", content_type)
-		.body(body.to_string().into())
-		.unwrap_or_default();
+/// Parses a path prefix such as `/media`, dropping any trailing slash.
+fn path_prefix(path: &str) -> Result<String, &'static str> {
+	let path = path.trim_end_matches('/');
+	let valid = path.starts_with('/')
+		&& path[1..]
+			.split('/')
+			.all(|segment| !segment.is_empty() && segment.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+
+	if valid {
+		Ok(path.to_string())
+	} else {
+		Err("must be a path like /media")
+	}
+}
+
+/// Follows the share link at `path` to the post it points to. Share links
+/// carry a random slug instead of the post ID, so only Reddit can resolve them.
+async fn share_link(req: Request<Body>, path: String) -> Result<Response<Body>, String> {
+	let slug = path.rsplit('/').next().unwrap_or_default();
+	if !(8..16).contains(&slug.len()) || !slug.chars().all(|c| c.is_ascii_alphanumeric()) {
+		return error(req, "Nothing here".to_string()).await;
+	}
+
+	match canonical_path(path).await {
+		// Reddit may answer with a full URL, and adds tracking parameters to it
+		Ok(Some(location)) => match utils::reddit_path(&location).or(Some(location)).filter(|location| location.starts_with('/')) {
+			Some(location) => Ok(redirect(location.split('?').next().unwrap_or_default().to_string())),
+			None => error(req, "Share link points outside of Reddit").await,
+		},
+		Ok(None) => error(req, "Post ID is invalid. It may point to a post on a community that has been banned.").await,
+		Err(e) => error(req, e).await,
+	}
+}
+
+/// Serves a static resource. If the client's `If-None-Match` header shows that
+/// it already has the current version, a 304 without body is sent instead.
+fn resource(req: &Request<Body>, (body, etag): (Vec<u8>, String), content_type: &str, cache: bool) -> Result<Response<Body>, String> {
+	let not_modified = req
+		.headers()
+		.get(header::IF_NONE_MATCH)
+		.and_then(|val| val.to_str().ok())
+		.is_some_and(|tags| tags.split(',').map(str::trim).any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag));
+
+	let mut res = if not_modified {
+		Response::builder().status(304).body(Body::empty())
+	} else {
+		Response::builder().status(200).header("content-type", content_type).body(body.into())
+	}
+	.unwrap_or_default();
+
+	if let Ok(val) = HeaderValue::from_str(&etag) {
+		res.headers_mut().insert(header::ETAG, val);
+	}
 
 	if cache {
 		if let Ok(val) = HeaderValue::from_str("public, max-age=1209600, s-maxage=86400") {
@@ -89,25 +138,20 @@ async fn resource(body: &str, content_type: &str, cache: bool) -> Result<Respons
 	Ok(res)
 }
 
-async fn style() -> Result<Response<Body>, String> {
-	let mut res = include_str!("../static/style.css").to_string();
+// The main stylesheet with all themes appended
+#[cached(time = 60)]
+fn stylesheet() -> (Vec<u8>, String) {
+	let mut res = String::from_utf8_lossy(&asset("style.css", include_bytes!("../static/style.css")).0).to_string();
 	for file in ThemeAssets::iter() {
 		res.push('\n');
 		let theme = ThemeAssets::get(file.as_ref()).unwrap();
-		res.push_str(std::str::from_utf8(theme.data.as_ref()).unwrap());
+		res.push_str(&String::from_utf8_lossy(&asset(&format!("themes/{}", file), theme.data.as_ref()).0));
 	}
-	Ok(
-		Response::builder()
-			.status(200)
-			.header("content-type", "text/css")
-			.header("Cache-Control", "public, max-age=1209600, s-maxage=86400")
-			.body(res.to_string().into())
-			.unwrap_or_default(),
-	)
+	let tag = etag(res.as_bytes());
+	(res.into_bytes(), tag)
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
 	let matches = Command::new("Libreddit")
 		.version(env!("CARGO_PKG_VERSION"))
 		.about("Private front-end for Reddit written in Rust ")
@@ -115,9 +159,38 @@ async fn main() {
 			Arg::new("redirect-https")
 				.short('r')
 				.long("redirect-https")
-				.help("Redirect all HTTP requests to HTTPS (no longer functional)")
+				.help("Redirect all HTTP requests to HTTPS (requires a reverse proxy that sets X-Forwarded-Proto, listed in --trusted-proxies)")
 				.num_args(0),
 		)
+		.arg(
+			Arg::new("redirect-https-exempt")
+				.long("redirect-https-exempt")
+				.value_name("PATHS")
+				.env("LIBREDDIT_REDIRECT_HTTPS_EXEMPT")
+				.help("Comma-separated path prefixes that are not redirected to HTTPS (e.g. /healthz,/metrics)")
+				.value_delimiter(',')
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("auth")
+				.long("auth")
+				.value_name("USER:HASH")
+				.env("LIBREDDIT_AUTH")
+				.help("Require HTTP basic auth as USER, whose password has the hex-encoded SHA-256 HASH (e.g. from `printf %s password | sha256sum`)")
+				.value_parser(|auth: &str| auth.parse::<server::BasicAuth>())
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("trusted-proxies")
+				.long("trusted-proxies")
+				.value_name("CIDRS")
+				.env("LIBREDDIT_TRUSTED_PROXIES")
+				.help("Comma-separated addresses or CIDR blocks of reverse proxies whose X-Forwarded-For and X-Forwarded-Proto headers are trusted. Defaults to proxies on the same machine")
+				.value_parser(|net: &str| net.parse::<server::IpNet>())
+				.default_values(["127.0.0.0/8", "::1"])
+				.value_delimiter(',')
+				.num_args(1),
+		)
 		.arg(
 			Arg::new("address")
 				.short('a')
@@ -138,28 +211,298 @@ async fn main() {
 				.action(ArgAction::Set)
 				.num_args(1),
 		)
+		.arg(
+			Arg::new("workers")
+				.long("workers")
+				.value_name("THREADS")
+				.env("LIBREDDIT_WORKERS")
+				.help("Number of worker threads handling requests [default: one per CPU core]")
+				.value_parser(clap::value_parser!(u16).range(1..))
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("shutdown-timeout")
+				.long("shutdown-timeout")
+				.value_name("SECONDS")
+				.env("LIBREDDIT_SHUTDOWN_TIMEOUT")
+				.help("Seconds to let in-flight requests finish after receiving SIGTERM or CTRL+C")
+				.default_value("30")
+				.value_parser(clap::value_parser!(u64))
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("max-comment-depth")
+				.long("max-comment-depth")
+				.value_name("DEPTH")
+				.env("LIBREDDIT_MAX_COMMENT_DEPTH")
+				.help("Deepest level of replies shown on a post page. Deeper replies get a link to continue the thread")
+				.value_parser(clap::value_parser!(u64).range(1..))
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("media-cache-size")
+				.long("media-cache-size")
+				.value_name("MEGABYTES")
+				.env("LIBREDDIT_MEDIA_CACHE_SIZE")
+				.help("Megabytes of memory to cache proxied thumbnails, emoji and other media in, for as long as Reddit allows. 0 disables the cache")
+				.default_value("0")
+				.value_parser(clap::value_parser!(u64))
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("pool-max-idle")
+				.long("pool-max-idle")
+				.value_name("CONNECTIONS")
+				.env("LIBREDDIT_POOL_MAX_IDLE")
+				.help("Most idle connections to keep open to each Reddit host [default: 4 per worker thread]")
+				.value_parser(clap::value_parser!(u64))
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("pool-idle-timeout")
+				.long("pool-idle-timeout")
+				.value_name("SECONDS")
+				.env("LIBREDDIT_POOL_IDLE_TIMEOUT")
+				.help("Seconds an idle connection to Reddit is kept open for reuse")
+				.default_value("90")
+				.value_parser(clap::value_parser!(u64).range(1..))
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("fetch-retries")
+				.long("fetch-retries")
+				.value_name("COUNT")
+				.env("LIBREDDIT_FETCH_RETRIES")
+				.help("Times to retry a Reddit request that failed with a server or connection error")
+				.default_value("2")
+				.value_parser(clap::value_parser!(u32))
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("fetch-retry-delay")
+				.long("fetch-retry-delay")
+				.value_name("MILLISECONDS")
+				.env("LIBREDDIT_FETCH_RETRY_DELAY")
+				.help("Wait before the first retry of a failed Reddit request, doubling with every retry")
+				.default_value("250")
+				.value_parser(clap::value_parser!(u64))
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("default-sort")
+				.long("default-sort")
+				.value_name("SORT")
+				.env("LIBREDDIT_DEFAULT_POST_SORT")
+				.help("Sort for subreddit feeds and the front page when neither the URL nor the user's settings pick one. Same as the LIBREDDIT_DEFAULT_POST_SORT instance setting")
+				.value_parser(["hot", "new", "top", "rising", "controversial"])
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("max-subscriptions")
+				.long("max-subscriptions")
+				.value_name("COUNT")
+				.env("LIBREDDIT_MAX_SUBSCRIPTIONS")
+				.help("Most feeds a user can subscribe to, keeping the subscriptions cookie under browser size limits")
+				.default_value("250")
+				.value_parser(clap::value_parser!(u64).range(1..))
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("user-agent")
+				.long("user-agent")
+				.value_name("STRING")
+				.env("LIBREDDIT_USER_AGENT")
+				.help("User-Agent to send with requests to Reddit")
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("enable-reader")
+				.long("enable-reader")
+				.help("Enable the experimental reader mode, which fetches linked articles on the server and shows their text")
+				.num_args(0),
+		)
+		.arg(
+			Arg::new("enable-debug")
+				.long("enable-debug")
+				.help("Allow ?debug=json on posts and listings to show the JSON Reddit returned for them")
+				.num_args(0),
+		)
+		.arg(
+			Arg::new("hotlink-media")
+				.long("hotlink-media")
+				.help("Have browsers load images and videos straight from Reddit instead of through this instance. Saves bandwidth, but Reddit sees users' IPs")
+				.num_args(0),
+		)
+		.arg(
+			Arg::new("log-upstream")
+				.long("log-upstream")
+				.help("Log every request made to Reddit with its status, duration and the client address that caused it. The URLs include what users search for")
+				.num_args(0),
+		)
+		.arg(
+			Arg::new("disable-subscriptions")
+				.long("disable-subscriptions")
+				.help("Turn off subscriptions, ignoring subscription cookies and showing the default front page to everyone")
+				.num_args(0),
+		)
+		.arg(
+			Arg::new("check-upstream")
+				.long("check-upstream")
+				.help("Check that Reddit can be reached on startup, warning if not. Pages show a \"starting up\" error until it can")
+				.num_args(0),
+		)
+		.arg(
+			Arg::new("canonical-links")
+				.long("canonical-links")
+				.help("Add <link rel=\"canonical\"> tags pointing to reddit.com to posts, subreddits and user profiles")
+				.num_args(0),
+		)
+		.arg(
+			Arg::new("static-dir")
+				.long("static-dir")
+				.value_name("DIR")
+				.env("LIBREDDIT_STATIC_DIR")
+				.help("Serve static assets (style.css, themes/, icons) from this directory, falling back to the built-in copies")
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("frame-options")
+				.long("frame-options")
+				.value_name("POLICY")
+				.env("LIBREDDIT_FRAME_OPTIONS")
+				.help("Who may embed Libreddit in a frame: nobody (DENY), this origin only (SAMEORIGIN), or anyone (ALLOW)")
+				.default_value("DENY")
+				.value_parser(["DENY", "SAMEORIGIN", "ALLOW"])
+				.ignore_case(true)
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("csp-append")
+				.long("csp-append")
+				.value_name("DIRECTIVES")
+				.env("LIBREDDIT_CSP_APPEND")
+				.help("Extra Content-Security-Policy directives, e.g. \"script-src https://example.com\". Sources are added to matching built-in directives")
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("base-path")
+				.long("base-path")
+				.value_name("PATH")
+				.env("LIBREDDIT_BASE_PATH")
+				.help("Path Libreddit is hosted under behind a reverse proxy, e.g. /reddit for example.com/reddit/")
+				.value_parser(path_prefix)
+				.num_args(1),
+		)
+		.arg(
+			Arg::new("proxy-path")
+				.long("proxy-path")
+				.value_name("PATH")
+				.env("LIBREDDIT_PROXY_PATH")
+				.help("Serve proxied media (/img/, /vid/, /preview/ and so on) under this path instead, e.g. /media")
+				.value_parser(path_prefix)
+				.num_args(1),
+		)
 		.arg(
 			Arg::new("hsts")
 				.short('H')
 				.long("hsts")
 				.value_name("EXPIRE_TIME")
-				.help("HSTS header to tell browsers that this site should only be accessed over HTTPS")
+				.help("HSTS header to tell browsers that this site should only be accessed over HTTPS. Only sent on requests served over HTTPS")
 				.default_value("604800")
+				.value_parser(clap::value_parser!(u64))
 				.num_args(1),
 		)
+		.arg(
+			Arg::new("hsts-include-subdomains")
+				.long("hsts-include-subdomains")
+				.help("Apply the HSTS header to every subdomain of this site too")
+				.num_args(0),
+		)
 		.get_matches();
 
+	// Size the runtime's worker pool before anything starts on it
+	let mut runtime = tokio::runtime::Builder::new_multi_thread();
+	if let Some(workers) = matches.get_one::<u16>("workers") {
+		runtime.worker_threads(usize::from(*workers));
+	}
+
+	match runtime.enable_all().build() {
+		Ok(runtime) => runtime.block_on(run(matches)),
+		Err(e) => eprintln!("Couldn't start the runtime: {}", e),
+	}
+}
+
+async fn run(matches: ArgMatches) {
 	let address = matches.get_one::<String>("address").unwrap();
 	let port = matches.get_one::<String>("port").unwrap();
-	let hsts = matches.get_one("hsts").map(|m: &String| m.as_str());
+	let hsts = matches.get_one::<u64>("hsts").copied();
 
 	let listener = [address, ":", port].concat();
 
+	// Keep enough connections around for every worker to have a few requests to Reddit in flight
+	let workers = matches.get_one::<u16>("workers").map_or_else(
+		|| std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get),
+		|workers| usize::from(*workers),
+	);
+	let max_idle = matches.get_one::<u64>("pool-max-idle").map_or(workers * client::POOL_IDLE_PER_WORKER, |max| *max as usize);
+	let _ = client::POOL_MAX_IDLE_PER_HOST.set(max_idle);
+	let _ = client::POOL_IDLE_TIMEOUT.set(std::time::Duration::from_secs(*matches.get_one::<u64>("pool-idle-timeout").unwrap()));
+
+	let _ = media_cache::CAPACITY.set(*matches.get_one::<u64>("media-cache-size").unwrap() as usize * 1024 * 1024);
+
+	let _ = client::FETCH_RETRIES.set(*matches.get_one::<u32>("fetch-retries").unwrap());
+	let _ = client::FETCH_RETRY_DELAY.set(std::time::Duration::from_millis(*matches.get_one::<u64>("fetch-retry-delay").unwrap()));
+
+	if let Some(user_agent) = matches.get_one::<String>("user-agent") {
+		let _ = client::USER_AGENT.set(user_agent.clone());
+	}
+
+	if let Some(path) = matches.get_one::<String>("base-path") {
+		let _ = utils::BASE_PATH.set(path.clone());
+	}
+
+	if let Some(path) = matches.get_one::<String>("proxy-path") {
+		let _ = utils::PROXY_PATH.set(path.clone());
+	}
+
+	if let Some(sort) = matches.get_one::<String>("default-sort") {
+		let _ = subreddit::DEFAULT_SORT.set(sort.clone());
+	}
+
+	let _ = subreddit::MAX_SUBSCRIPTIONS.set(*matches.get_one::<u64>("max-subscriptions").unwrap() as usize);
+
+	if let Some(depth) = matches.get_one::<u64>("max-comment-depth") {
+		let _ = post::MAX_COMMENT_DEPTH.set(*depth as usize);
+	}
+
+	if matches.get_flag("canonical-links") {
+		utils::CANONICAL_LINKS.store(true, std::sync::atomic::Ordering::Relaxed);
+	}
+
+	if let Some(dir) = matches.get_one::<String>("static-dir") {
+		let _ = STATIC_DIR.set(PathBuf::from(dir));
+	}
+
 	println!("Starting Libreddit...");
 
 	// Begin constructing a server
 	let mut app = server::Server::new();
 
+	app.redirect_https = matches.get_flag("redirect-https");
+	app.https_exempt_paths = matches
+		.get_many::<String>("redirect-https-exempt")
+		.map(|paths| paths.map(|p| p.trim().to_string()).collect())
+		.unwrap_or_default();
+	app.trusted_proxies = matches.get_many::<server::IpNet>("trusted-proxies").map(|nets| nets.cloned().collect()).unwrap_or_default();
+	let hsts_set = matches.value_source("hsts") == Some(ValueSource::CommandLine);
+	if matches.value_source("trusted-proxies") == Some(ValueSource::DefaultValue) && (app.redirect_https || hsts_set) {
+		println!("Only proxies on this machine are trusted to report HTTPS. Pass --trusted-proxies if yours runs elsewhere.");
+	}
+	app.auth = matches.get_one::<server::BasicAuth>("auth").cloned();
+	app.auth_exempt_paths = vec!["/healthz".to_string()];
+	app.shutdown_timeout = tokio::time::Duration::from_secs(*matches.get_one::<u64>("shutdown-timeout").unwrap());
+	app.base_path = utils::base_path().to_string();
+
 	// Force evaluation of statics. In instance_info case, we need to evaluate
 	// the timestamp so deploy date is accurate - in config case, we need to
 	// evaluate the configuration to avoid paying penalty at first request.
@@ -167,64 +510,139 @@ async fn main() {
 	Lazy::force(&config::CONFIG);
 	Lazy::force(&instance_info::INSTANCE_INFO);
 
+	// Framing is restricted by both X-Frame-Options and the CSP's frame-ancestors
+	let (frame_options, frame_ancestors) = match matches.get_one::<String>("frame-options").map(|f| f.to_uppercase()).as_deref() {
+		Some("SAMEORIGIN") => (Some("SAMEORIGIN"), " frame-ancestors 'self';"),
+		Some("ALLOW") => (None, ""),
+		_ => (Some("DENY"), " frame-ancestors 'none';"),
+	};
+	let mut csp = format!("default-src 'none'; font-src 'self'; script-src 'self' blob:; manifest-src 'self'; media-src 'self' data: blob: about:; style-src 'self' 'unsafe-inline'; base-uri 'none'; img-src 'self' data:; form-action 'self';{} connect-src 'self'; worker-src blob:;", frame_ancestors);
+
+	client::LOG_UPSTREAM.store(matches.get_flag("log-upstream"), std::sync::atomic::Ordering::Relaxed);
+	utils::DISABLE_SUBSCRIPTIONS.store(matches.get_flag("disable-subscriptions"), std::sync::atomic::Ordering::Relaxed);
+	utils::ENABLE_DEBUG.store(matches.get_flag("enable-debug"), std::sync::atomic::Ordering::Relaxed);
+
+	// Hotlinked media comes from Reddit's CDNs, so the CSP has to allow them.
+	// HLS.js fetches video segments itself, hence connect-src.
+	if matches.get_flag("hotlink-media") {
+		utils::HOTLINK_MEDIA.store(true, std::sync::atomic::Ordering::Relaxed);
+		let hosts = |list: &[&str]| list.iter().map(|host| format!("https://{}", host)).collect::<Vec<_>>().join(" ");
+		let videos = hosts(utils::HOTLINKED_VIDEO_HOSTS);
+		csp = server::extend_csp(
+			&csp,
+			&format!(
+				"img-src {}; media-src {} {}; connect-src {};",
+				hosts(utils::HOTLINKED_IMAGE_HOSTS),
+				videos,
+				hosts(utils::HOTLINKED_IMAGE_HOSTS),
+				videos
+			),
+		);
+	}
+
+	let csp = server::extend_csp(&csp, matches.get_one::<String>("csp-append").map_or("", String::as_str));
+
 	// Define default headers (added to all responses)
 	app.default_headers = headers! {
 		"Referrer-Policy" => "no-referrer",
 		"X-Content-Type-Options" => "nosniff",
-		"X-Frame-Options" => "DENY",
-		"Content-Security-Policy" => "default-src 'none'; font-src 'self'; script-src 'self' blob:; manifest-src 'self'; media-src 'self' data: blob: about:; style-src 'self' 'unsafe-inline'; base-uri 'none'; img-src 'self' data:; form-action 'self'; frame-ancestors 'none'; connect-src 'self'; worker-src blob:;"
+		"Content-Security-Policy" => &csp
 	};
 
+	if let Some(frame_options) = frame_options {
+		app.default_headers.insert("X-Frame-Options", HeaderValue::from_static(frame_options));
+	}
+
 	if let Some(expire_time) = hsts {
-		if let Ok(val) = HeaderValue::from_str(&format!("max-age={}", expire_time)) {
+		let subdomains = if matches.get_flag("hsts-include-subdomains") { "; includeSubDomains" } else { "" };
+		if let Ok(val) = HeaderValue::from_str(&format!("max-age={}{}", expire_time, subdomains)) {
 			app.default_headers.insert("Strict-Transport-Security", val);
 		}
 	}
 
 	// Read static files
-	app.at("/style.css").get(|_| style().boxed());
+	app.at("/style.css").get(|r| async move { resource(&r, stylesheet(), "text/css", true) }.boxed());
 	app
 		.at("/manifest.json")
-		.get(|_| resource(include_str!("../static/manifest.json"), "application/json", false).boxed());
-	app.at("/robots.txt").get(|_| {
-		resource(
-			if match config::get_setting("LIBREDDIT_ROBOTS_DISABLE_INDEXING") {
+		.get(|r| async move { resource(&r, asset("manifest.json", include_bytes!("../static/manifest.json")), "application/json", false) }.boxed());
+	app
+		.at("/healthz")
+		.get(|_| async move { Ok(Response::builder().status(200).header("content-type", "text/plain").body("OK".into()).unwrap_or_default()) }.boxed());
+	app.at("/robots.txt").get(|r| {
+		async move {
+			let body = if match config::get_setting("LIBREDDIT_ROBOTS_DISABLE_INDEXING") {
 				Some(val) => val == "on",
 				None => false,
 			} {
 				"User-agent: *\nDisallow: /"
 			} else {
 				"User-agent: *\nDisallow: /u/\nDisallow: /user/"
-			},
-			"text/plain",
-			true,
-		)
+			};
+			resource(&r, (body.as_bytes().to_vec(), etag(body.as_bytes())), "text/plain", true)
+		}
 		.boxed()
 	});
-	app.at("/favicon.ico").get(|_| favicon().boxed());
-	app.at("/logo.png").get(|_| pwa_logo().boxed());
-	app.at("/Inter.var.woff2").get(|_| font().boxed());
-	app.at("/touch-icon-iphone.png").get(|_| iphone_logo().boxed());
-	app.at("/apple-touch-icon.png").get(|_| iphone_logo().boxed());
+	app
+		.at("/favicon.ico")
+		.get(|r| async move { resource(&r, asset("favicon.ico", include_bytes!("../static/favicon.ico")), "image/vnd.microsoft.icon", true) }.boxed());
+	// Required for the manifest to be valid
+	app
+		.at("/logo.png")
+		.get(|r| async move { resource(&r, asset("logo.png", include_bytes!("../static/logo.png")), "image/png", false) }.boxed());
+	app
+		.at("/Inter.var.woff2")
+		.get(|r| async move { resource(&r, asset("Inter.var.woff2", include_bytes!("../static/Inter.var.woff2")), "font/woff2", true) }.boxed());
+	// Required for iOS App Icons
+	app
+		.at("/touch-icon-iphone.png")
+		.get(|r| async move { resource(&r, asset("apple-touch-icon.png", include_bytes!("../static/apple-touch-icon.png")), "image/png", false) }.boxed());
+	app
+		.at("/apple-touch-icon.png")
+		.get(|r| async move { resource(&r, asset("apple-touch-icon.png", include_bytes!("../static/apple-touch-icon.png")), "image/png", false) }.boxed());
 	app
 		.at("/playHLSVideo.js")
-		.get(|_| resource(include_str!("../static/playHLSVideo.js"), "text/javascript", false).boxed());
+		.get(|r| async move { resource(&r, asset("playHLSVideo.js", include_bytes!("../static/playHLSVideo.js")), "text/javascript", false) }.boxed());
+	app
+		.at("/copyLink.js")
+		.get(|r| async move { resource(&r, asset("copyLink.js", include_bytes!("../static/copyLink.js")), "text/javascript", false) }.boxed());
 	app
 		.at("/hls.min.js")
-		.get(|_| resource(include_str!("../static/hls.min.js"), "text/javascript", false).boxed());
+		.get(|r| async move { resource(&r, asset("hls.min.js", include_bytes!("../static/hls.min.js")), "text/javascript", false) }.boxed());
 
 	// Proxy media through Libreddit
-	app.at("/vid/:id/:size").get(|r| proxy(r, "https://v.redd.it/{id}/DASH_{size}").boxed());
-	app.at("/hls/:id/*path").get(|r| proxy(r, "https://v.redd.it/{id}/{path}").boxed());
-	app.at("/img/*path").get(|r| proxy(r, "https://i.redd.it/{path}").boxed());
-	app.at("/thumb/:point/:id").get(|r| proxy(r, "https://{point}.thumbs.redditmedia.com/{id}").boxed());
-	app.at("/emoji/:id/:name").get(|r| proxy(r, "https://emoji.redditmedia.com/{id}/{name}").boxed());
+	let media = |route: &str| [utils::proxy_path(), route].concat();
+	app.at(&media("/vid/:id/:size")).get(|r| proxy(r, "https://v.redd.it/{id}/DASH_{size}").boxed());
+	app.at(&media("/hls/:id/*path")).get(|r| proxy(r, "https://v.redd.it/{id}/{path}").boxed());
+	app.at(&media("/img/*path")).get(|r| proxy(r, "https://i.redd.it/{path}").boxed());
+	app.at(&media("/thumb/:point/:id")).get(|r| proxy(r, "https://{point}.thumbs.redditmedia.com/{id}").boxed());
+	app.at(&media("/emoji/:id/:name")).get(|r| proxy(r, "https://emoji.redditmedia.com/{id}/{name}").boxed());
 	app
-		.at("/preview/:loc/award_images/:fullname/:id")
+		.at(&media("/preview/:loc/award_images/:fullname/:id"))
 		.get(|r| proxy(r, "https://{loc}view.redd.it/award_images/{fullname}/{id}").boxed());
-	app.at("/preview/:loc/:id").get(|r| proxy(r, "https://{loc}view.redd.it/{id}").boxed());
-	app.at("/style/*path").get(|r| proxy(r, "https://styles.redditmedia.com/{path}").boxed());
-	app.at("/static/*path").get(|r| proxy(r, "https://www.redditstatic.com/{path}").boxed());
+	app.at(&media("/preview/:loc/:id")).get(|r| proxy(r, "https://{loc}view.redd.it/{id}").boxed());
+	app.at(&media("/style/*path")).get(|r| proxy(r, "https://styles.redditmedia.com/{path}").boxed());
+	app.at(&media("/static/*path")).get(|r| proxy(r, "https://www.redditstatic.com/{path}").boxed());
+
+	// Map a Reddit URL to its Libreddit path, for browser extensions
+	app.at("/api/resolve").get(|r| {
+		async move {
+			let url = utils::param(&format!("?{}", r.uri().query().unwrap_or_default()), "url").unwrap_or_default();
+			let (status, body) = match utils::reddit_path(&url) {
+				Some(path) => (200, serde_json::json!({ "url": url, "path": format!("{}{}", utils::base_path(), path) })),
+				None => (400, serde_json::json!({ "url": url, "error": "Not a Reddit URL" })),
+			};
+
+			Ok(
+				Response::builder()
+					.status(status)
+					.header("content-type", "application/json")
+					.header("Access-Control-Allow-Origin", "*")
+					.body(body.to_string().into())
+					.unwrap_or_default(),
+			)
+		}
+		.boxed()
+	});
 
 	// Browse user profile
 	app
@@ -236,6 +654,10 @@ async fn main() {
 	app.at("/user/[deleted]").get(|req| error(req, "User has deleted their account".to_string()).boxed());
 	app.at("/user/:name").get(|r| user::profile(r).boxed());
 	app.at("/user/:name/:listing").get(|r| user::profile(r).boxed());
+	app.at("/user/:name/m/:multi").get(|r| subreddit::multireddit(r).boxed());
+	app.at("/user/:name/m/:multi/:sort").get(|r| subreddit::multireddit(r).boxed());
+	app.at("/u/:name/m/:multi").get(|r| subreddit::multireddit(r).boxed());
+	app.at("/u/:name/m/:multi/:sort").get(|r| subreddit::multireddit(r).boxed());
 	app.at("/user/:name/comments/:id").get(|r| post::item(r).boxed());
 	app.at("/user/:name/comments/:id/:title").get(|r| post::item(r).boxed());
 	app.at("/user/:name/comments/:id/:title/:comment_id").get(|r| post::item(r).boxed());
@@ -244,6 +666,15 @@ async fn main() {
 	app.at("/settings").get(|r| settings::get(r).boxed()).post(|r| settings::set(r).boxed());
 	app.at("/settings/restore").get(|r| settings::restore(r).boxed());
 	app.at("/settings/update").get(|r| settings::update(r).boxed());
+	app.at("/settings/seen/mark").post(|r| settings::seen(r).boxed());
+	app.at("/settings/seen/clear").post(|r| settings::seen(r).boxed());
+	app.at("/settings/import").post(|r| saved::import(r).boxed());
+
+	// Posts imported from a Reddit data export
+	app.at("/saved").get(|r| saved::list(r).boxed());
+	app.at("/upvoted").get(|r| saved::list(r).boxed());
+	app.at("/saved/export").get(|r| saved::export(r).boxed());
+	app.at("/upvoted/export").get(|r| saved::export(r).boxed());
 
 	// Subreddit services
 	app
@@ -283,9 +714,15 @@ async fn main() {
 		.at("/r/:sub/w/*page")
 		.get(|r| async move { Ok(redirect(format!("/r/{}/wiki/{}", r.param("sub").unwrap_or_default(), r.param("wiki").unwrap_or_default()))) }.boxed());
 	app.at("/r/:sub/wiki").get(|r| subreddit::wiki(r).boxed());
+	app.at("/r/:sub/wiki/revisions/*page").get(|r| subreddit::wiki_revisions(r).boxed());
 	app.at("/r/:sub/wiki/*page").get(|r| subreddit::wiki(r).boxed());
 
 	app.at("/r/:sub/about/sidebar").get(|r| subreddit::sidebar(r).boxed());
+	app.at("/r/:sub/about/moderators").get(|r| subreddit::moderators(r).boxed());
+	app.at("/feed/:name").get(|r| subreddit::feed(r).boxed());
+	app.at("/feed/:name/:sort").get(|r| subreddit::feed(r).boxed());
+
+	app.at("/r/:sub/about/rules").get(|r| subreddit::rules(r).boxed());
 
 	app.at("/r/:sub/:sort").get(|r| subreddit::community(r).boxed());
 
@@ -298,6 +735,7 @@ async fn main() {
 		.at("/w/*page")
 		.get(|r| async move { Ok(redirect(format!("/wiki/{}", r.param("page").unwrap_or_default()))) }.boxed());
 	app.at("/wiki").get(|r| subreddit::wiki(r).boxed());
+	app.at("/wiki/revisions/*page").get(|r| subreddit::wiki_revisions(r).boxed());
 	app.at("/wiki/*page").get(|r| subreddit::wiki(r).boxed());
 
 	// Search all of Reddit
@@ -306,30 +744,36 @@ async fn main() {
 	// Handle about pages
 	app.at("/about").get(|req| error(req, "About pages aren't added yet".to_string()).boxed());
 
+	// Experimental reader mode for link posts
+	if matches.get_flag("enable-reader") {
+		reader::ENABLED.store(true, std::sync::atomic::Ordering::Relaxed);
+		app.at("/reader").get(|r| reader::page(r).boxed());
+	}
+
 	// Instance info page
 	app.at("/info").get(|r| instance_info::instance_info(r).boxed());
 	app.at("/info.:extension").get(|r| instance_info::instance_info(r).boxed());
-	
-	// Handle obfuscated share links. 
+
+	// Handle obfuscated share links.
 	// Note that this still forces the server to follow the share link to get to the post, so maybe this wants to be updated with a warning before it follow it
 	app.at("/r/:sub/s/:id").get(|req: Request<Body>| {
-		Box::pin(async move {
-			let sub = req.param("sub").unwrap_or_default();
-			match req.param("id").as_deref() {
-				
-				// Share link
-				Some(id) if (8..12).contains(&id.len()) => match canonical_path(format!("/r/{}/s/{}", sub, id)).await {
-					Ok(Some(path)) => Ok(redirect(path.split('?').next().unwrap_or_default().to_string())),
-					Ok(None) => error(req, "Post ID is invalid. It may point to a post on a community that has been banned.").await,
-					Err(e) => error(req, e).await,
-				},
-
-				// Error message for unknown pages
-				_ => error(req, "Nothing here".to_string()).await,
-			}
-		})
+		let path = format!("/r/{}/s/{}", req.param("sub").unwrap_or_default(), req.param("id").unwrap_or_default());
+		share_link(req, path).boxed()
+	});
+	app.at("/u/:name/s/:id").get(|req: Request<Body>| {
+		let path = format!("/u/{}/s/{}", req.param("name").unwrap_or_default(), req.param("id").unwrap_or_default());
+		share_link(req, path).boxed()
+	});
+	app.at("/user/:name/s/:id").get(|req: Request<Body>| {
+		let path = format!("/u/{}/s/{}", req.param("name").unwrap_or_default(), req.param("id").unwrap_or_default());
+		share_link(req, path).boxed()
 	});
-	
+
+	// Gallery links use the ID of the post the gallery belongs to
+	app
+		.at("/gallery/:id")
+		.get(|r| async move { Ok(redirect(format!("/comments/{}", r.param("id").unwrap_or_default()))) }.boxed());
+
 	app.at("/:id").get(|req: Request<Body>| {
 		Box::pin(async move {
 			match req.param("id").as_deref() {
@@ -338,10 +782,10 @@ async fn main() {
 
 				// Short link for post
 				Some(id) if (5..8).contains(&id.len()) => match canonical_path(format!("/{}", id)).await {
-					Ok(path_opt) => match path_opt {
-						Some(path) => Ok(redirect(path)),
-						None => error(req, "Post ID is invalid. It may point to a post on a community that has been banned.").await,
-					},
+					// Only follow Reddit's redirect if it lands on a post, as the
+					// "ID" may just as well be a subreddit or some other page.
+					Ok(Some(path)) if path.contains("/comments/") => Ok(redirect(path.split('?').next().unwrap_or_default().to_string())),
+					Ok(_) => error(req, "Post ID is invalid. It may point to a post on a community that has been banned.").await,
 					Err(e) => error(req, e).await,
 				},
 
@@ -356,10 +800,17 @@ async fn main() {
 
 	println!("Running Libreddit v{} on {}!", env!("CARGO_PKG_VERSION"), listener);
 
+	if matches.get_flag("check-upstream") {
+		utils::UPSTREAM_UNCONFIRMED.store(true, std::sync::atomic::Ordering::Relaxed);
+		tokio::spawn(client::check_upstream());
+	}
+
 	let server = app.listen(listener);
 
-	// Run this server for... forever!
+	// Run this server until it is asked to shut down
 	if let Err(e) = server.await {
 		eprintln!("Server error: {}", e);
 	}
+
+	println!("Libreddit has shut down.");
 }
@@ -0,0 +1,532 @@
+// Renders the LaTeX math in post and comment bodies as MathML, so formulas
+// display without any client-side JavaScript. Only a common subset of LaTeX is
+// understood; anything else is left as written.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Replaces the `$...$` and `$$...$$` math in the text of `html` with MathML.
+/// Tags, code blocks and math that doesn't parse are left alone.
+pub fn render(html: &str) -> String {
+	let mut out = String::with_capacity(html.len());
+	let mut code_depth = 0;
+	let mut rest = html;
+
+	while !rest.is_empty() {
+		if rest.starts_with('<') {
+			let end = rest.find('>').map_or(rest.len(), |i| i + 1);
+			let tag = &rest[..end];
+			if tag.starts_with("<code") || tag.starts_with("<pre") {
+				code_depth += 1;
+			} else if (tag.starts_with("</code") || tag.starts_with("</pre")) && code_depth > 0 {
+				code_depth -= 1;
+			}
+			out.push_str(tag);
+			rest = &rest[end..];
+		} else {
+			let end = rest.find('<').unwrap_or(rest.len());
+			if code_depth > 0 {
+				out.push_str(&rest[..end]);
+			} else {
+				render_text(&rest[..end], &mut out);
+			}
+			rest = &rest[end..];
+		}
+	}
+
+	out
+}
+
+// Renders the math in a run of (HTML-escaped) text between two tags
+fn render_text(text: &str, out: &mut String) {
+	let mut rest = text;
+
+	while let Some(start) = rest.find('$') {
+		out.push_str(&rest[..start]);
+		let after = &rest[start..];
+
+		let Some((tex, display, len)) = math_span(after) else {
+			out.push('$');
+			rest = &after[1..];
+			continue;
+		};
+
+		match to_mathml(&unescape(tex), display) {
+			Some(mathml) => out.push_str(&mathml),
+			None => out.push_str(&after[..len]),
+		}
+		rest = &after[len..];
+	}
+
+	out.push_str(rest);
+}
+
+// Finds the math starting at the `$` at the beginning of `text`, returning the
+// TeX inside, whether it's display math and the length of the whole span.
+// Inline math can't start or end with a space and can't be followed by a
+// digit, so prices like "$5 or $10" aren't mistaken for math.
+fn math_span(text: &str) -> Option<(&str, bool, usize)> {
+	if let Some(inner) = text.strip_prefix("$$") {
+		let end = inner.find("$$")?;
+		let tex = &inner[..end];
+		return (!tex.trim().is_empty()).then_some((tex, true, end + 4));
+	}
+
+	let inner = &text[1..];
+	let end = inner.find('$')?;
+	let tex = &inner[..end];
+	let followed_by_digit = inner[end + 1..].starts_with(|c: char| c.is_ascii_digit());
+
+	if tex.is_empty() || tex.starts_with(char::is_whitespace) || tex.ends_with(char::is_whitespace) || followed_by_digit {
+		None
+	} else {
+		Some((tex, false, end + 2))
+	}
+}
+
+fn unescape(text: &str) -> String {
+	text
+		.replace("&lt;", "<")
+		.replace("&gt;", ">")
+		.replace("&quot;", "\"")
+		.replace("&#39;", "'")
+		.replace("&amp;", "&")
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Converts TeX to a MathML `<math>` element, or `None` if it uses anything
+/// this module doesn't understand.
+pub fn to_mathml(tex: &str, display: bool) -> Option<String> {
+	let mut parser = Parser {
+		chars: tex.chars().peekable(),
+		depth: 0,
+	};
+	let body = parser.expr(None)?;
+	if parser.chars.peek().is_some() {
+		return None;
+	}
+
+	Some(format!("<math{}><mrow>{}</mrow></math>", if display { " display=\"block\"" } else { "" }, body))
+}
+
+enum Token {
+	Command(String),
+	Open,
+	Close,
+	Sup,
+	Sub,
+	Number(String),
+	Char(char),
+}
+
+/// How deeply groups and commands may nest. Beyond this, the TeX is left
+/// as it is rather than risk overflowing the stack.
+const MAX_DEPTH: usize = 32;
+
+struct Parser<'a> {
+	chars: Peekable<Chars<'a>>,
+	depth: usize,
+}
+
+impl Parser<'_> {
+	fn next_token(&mut self) -> Option<Token> {
+		while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+			self.chars.next();
+		}
+
+		let c = self.chars.next()?;
+		Some(match c {
+			'\\' => {
+				let mut name = String::new();
+				while let Some(&c) = self.chars.peek() {
+					if !c.is_ascii_alphabetic() {
+						break;
+					}
+					name.push(c);
+					self.chars.next();
+				}
+				// Commands made of a single symbol, like \{ or \,
+				if name.is_empty() {
+					name.push(self.chars.next()?);
+				}
+				Token::Command(name)
+			}
+			'{' => Token::Open,
+			'}' => Token::Close,
+			'^' => Token::Sup,
+			'_' => Token::Sub,
+			'0'..='9' | '.' => {
+				let mut number = c.to_string();
+				while let Some(&c) = self.chars.peek() {
+					if !c.is_ascii_digit() && c != '.' {
+						break;
+					}
+					number.push(c);
+					self.chars.next();
+				}
+				Token::Number(number)
+			}
+			c => Token::Char(c),
+		})
+	}
+
+	fn peek_token(&self) -> Option<Token> {
+		Parser {
+			chars: self.chars.clone(),
+			depth: 0,
+		}
+		.next_token()
+	}
+
+	// A sequence of terms, up to the end of the input or the closing `until`
+	fn expr(&mut self, until: Option<&str>) -> Option<String> {
+		let mut out = String::new();
+
+		loop {
+			match self.peek_token() {
+				None => return until.is_none().then_some(out),
+				Some(Token::Close) => return (until == Some("}")).then_some(out),
+				Some(Token::Command(name)) if name == "right" => return (until == Some("right")).then_some(out),
+				_ => out.push_str(&self.term()?),
+			}
+		}
+	}
+
+	// An atom with any superscript and subscript attached to it
+	fn term(&mut self) -> Option<String> {
+		let base = match self.peek_token() {
+			Some(Token::Sup | Token::Sub) => "<mrow></mrow>".to_string(),
+			_ => self.atom()?,
+		};
+
+		let (mut sup, mut sub) = (None, None);
+		loop {
+			match self.peek_token() {
+				Some(Token::Sup) if sup.is_none() => {
+					self.next_token();
+					sup = Some(self.atom()?);
+				}
+				Some(Token::Sub) if sub.is_none() => {
+					self.next_token();
+					sub = Some(self.atom()?);
+				}
+				_ => break,
+			}
+		}
+
+		Some(match (sub, sup) {
+			(None, None) => base,
+			(Some(sub), None) => format!("<msub>{}{}</msub>", base, sub),
+			(None, Some(sup)) => format!("<msup>{}{}</msup>", base, sup),
+			(Some(sub), Some(sup)) => format!("<msubsup>{}{}{}</msubsup>", base, sub, sup),
+		})
+	}
+
+	fn group(&mut self) -> Option<String> {
+		let inner = self.expr(Some("}"))?;
+		self.next_token();
+		Some(format!("<mrow>{}</mrow>", inner))
+	}
+
+	// Raw text up to the closing brace, for \text{...}
+	fn text(&mut self) -> Option<String> {
+		matches!(self.next_token()?, Token::Open).then_some(())?;
+		let mut text = String::new();
+		loop {
+			match self.chars.next()? {
+				'}' => return Some(text),
+				c => text.push(c),
+			}
+		}
+	}
+
+	fn atom(&mut self) -> Option<String> {
+		if self.depth == MAX_DEPTH {
+			return None;
+		}
+		self.depth += 1;
+		let atom = self.nested_atom();
+		self.depth -= 1;
+		atom
+	}
+
+	fn nested_atom(&mut self) -> Option<String> {
+		Some(match self.next_token()? {
+			Token::Open => self.group()?,
+			Token::Number(number) => format!("<mn>{}</mn>", number),
+			Token::Char(c) if c.is_alphabetic() => format!("<mi>{}</mi>", escape(&c.to_string())),
+			Token::Char(c) if "+-=<>()[]|/,;:!?'*".contains(c) => {
+				let c = if c == '-' {
+					'\u{2212}'
+				} else if c == '\'' {
+					'\u{2032}'
+				} else {
+					c
+				};
+				format!("<mo>{}</mo>", escape(&c.to_string()))
+			}
+			Token::Command(name) => self.command(&name)?,
+			_ => return None,
+		})
+	}
+
+	fn command(&mut self, name: &str) -> Option<String> {
+		Some(match name {
+			"frac" | "dfrac" | "tfrac" => {
+				let numerator = self.atom()?;
+				let denominator = self.atom()?;
+				format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+			}
+			"sqrt" => {
+				if self.chars.peek() == Some(&'[') {
+					self.chars.next();
+					let mut index = String::new();
+					loop {
+						match self.chars.next()? {
+							']' => break,
+							c => index.push(c),
+						}
+					}
+					let index = Parser {
+						chars: index.chars().peekable(),
+						depth: self.depth,
+					}
+					.expr(None)?;
+					let radicand = self.atom()?;
+					format!("<mroot>{}<mrow>{}</mrow></mroot>", radicand, index)
+				} else {
+					format!("<msqrt>{}</msqrt>", self.atom()?)
+				}
+			}
+			"left" => {
+				let open = self.delimiter()?;
+				let inner = self.expr(Some("right"))?;
+				self.next_token();
+				let close = self.delimiter()?;
+				format!("<mrow>{}{}{}</mrow>", open, inner, close)
+			}
+			"text" | "mathrm" | "textrm" | "operatorname" => format!("<mtext>{}</mtext>", escape(&self.text()?)),
+			"mathbf" | "textbf" => format!("<mrow mathvariant=\"bold\">{}</mrow>", self.atom()?),
+			"," | ":" | ";" | " " => "<mspace width=\"0.25em\"></mspace>".to_string(),
+			"quad" => "<mspace width=\"1em\"></mspace>".to_string(),
+			"qquad" => "<mspace width=\"2em\"></mspace>".to_string(),
+			"!" => String::new(),
+			"{" | "}" | "%" | "$" | "#" | "&" | "_" => format!("<mo>{}</mo>", escape(name)),
+			name if FUNCTIONS.contains(&name) => format!("<mi>{}</mi>", name),
+			name => {
+				let &(_, symbol) = SYMBOLS.iter().find(|(command, _)| *command == name)?;
+				if symbol.chars().all(char::is_alphabetic) {
+					format!("<mi>{}</mi>", symbol)
+				} else {
+					format!("<mo>{}</mo>", symbol)
+				}
+			}
+		})
+	}
+
+	// The delimiter after \left or \right
+	fn delimiter(&mut self) -> Option<String> {
+		Some(match self.next_token()? {
+			Token::Char('.') | Token::Number(_) => String::new(),
+			Token::Char(c) if "()[]|/".contains(c) => format!("<mo>{}</mo>", c),
+			Token::Command(name) if name == "{" || name == "}" => format!("<mo>{}</mo>", name),
+			Token::Command(name) if name == "langle" => "<mo>\u{27E8}</mo>".to_string(),
+			Token::Command(name) if name == "rangle" => "<mo>\u{27E9}</mo>".to_string(),
+			_ => return None,
+		})
+	}
+}
+
+// Function names set upright, like \sin
+const FUNCTIONS: &[&str] = &[
+	"sin", "cos", "tan", "cot", "sec", "csc", "arcsin", "arccos", "arctan", "sinh", "cosh", "tanh", "log", "ln", "lg", "exp", "lim", "max", "min", "sup", "inf", "det", "gcd",
+	"deg", "dim", "ker", "arg", "Pr",
+];
+
+const SYMBOLS: &[(&str, &str)] = &[
+	("alpha", "α"),
+	("beta", "β"),
+	("gamma", "γ"),
+	("delta", "δ"),
+	("epsilon", "ϵ"),
+	("varepsilon", "ε"),
+	("zeta", "ζ"),
+	("eta", "η"),
+	("theta", "θ"),
+	("vartheta", "ϑ"),
+	("iota", "ι"),
+	("kappa", "κ"),
+	("lambda", "λ"),
+	("mu", "μ"),
+	("nu", "ν"),
+	("xi", "ξ"),
+	("pi", "π"),
+	("rho", "ρ"),
+	("sigma", "σ"),
+	("tau", "τ"),
+	("upsilon", "υ"),
+	("phi", "ϕ"),
+	("varphi", "φ"),
+	("chi", "χ"),
+	("psi", "ψ"),
+	("omega", "ω"),
+	("Gamma", "Γ"),
+	("Delta", "Δ"),
+	("Theta", "Θ"),
+	("Lambda", "Λ"),
+	("Xi", "Ξ"),
+	("Pi", "Π"),
+	("Sigma", "Σ"),
+	("Upsilon", "Υ"),
+	("Phi", "Φ"),
+	("Psi", "Ψ"),
+	("Omega", "Ω"),
+	("pm", "±"),
+	("mp", "∓"),
+	("times", "×"),
+	("div", "÷"),
+	("cdot", "⋅"),
+	("ast", "∗"),
+	("star", "⋆"),
+	("circ", "∘"),
+	("bullet", "∙"),
+	("leq", "≤"),
+	("le", "≤"),
+	("geq", "≥"),
+	("ge", "≥"),
+	("neq", "≠"),
+	("ne", "≠"),
+	("approx", "≈"),
+	("equiv", "≡"),
+	("sim", "∼"),
+	("simeq", "≃"),
+	("cong", "≅"),
+	("propto", "∝"),
+	("ll", "≪"),
+	("gg", "≫"),
+	("to", "→"),
+	("rightarrow", "→"),
+	("leftarrow", "←"),
+	("gets", "←"),
+	("leftrightarrow", "↔"),
+	("Rightarrow", "⇒"),
+	("Leftarrow", "⇐"),
+	("Leftrightarrow", "⇔"),
+	("implies", "⟹"),
+	("iff", "⟺"),
+	("mapsto", "↦"),
+	("in", "∈"),
+	("notin", "∉"),
+	("ni", "∋"),
+	("subset", "⊂"),
+	("subseteq", "⊆"),
+	("supset", "⊃"),
+	("supseteq", "⊇"),
+	("cup", "∪"),
+	("cap", "∩"),
+	("setminus", "∖"),
+	("emptyset", "∅"),
+	("varnothing", "∅"),
+	("forall", "∀"),
+	("exists", "∃"),
+	("neg", "¬"),
+	("lnot", "¬"),
+	("land", "∧"),
+	("wedge", "∧"),
+	("lor", "∨"),
+	("vee", "∨"),
+	("oplus", "⊕"),
+	("otimes", "⊗"),
+	("perp", "⊥"),
+	("parallel", "∥"),
+	("mid", "∣"),
+	("angle", "∠"),
+	("partial", "∂"),
+	("nabla", "∇"),
+	("infty", "∞"),
+	("hbar", "ℏ"),
+	("ell", "ℓ"),
+	("Re", "ℜ"),
+	("Im", "ℑ"),
+	("aleph", "ℵ"),
+	("prime", "′"),
+	("degree", "°"),
+	("ldots", "…"),
+	("dots", "…"),
+	("cdots", "⋯"),
+	("vdots", "⋮"),
+	("ddots", "⋱"),
+	("sum", "∑"),
+	("prod", "∏"),
+	("coprod", "∐"),
+	("int", "∫"),
+	("iint", "∬"),
+	("iiint", "∭"),
+	("oint", "∮"),
+	("bigcup", "⋃"),
+	("bigcap", "⋂"),
+	("langle", "⟨"),
+	("rangle", "⟩"),
+	("lfloor", "⌊"),
+	("rfloor", "⌋"),
+	("lceil", "⌈"),
+	("rceil", "⌉"),
+];
+
+#[cfg(test)]
+mod tests {
+	use super::{render, to_mathml};
+
+	#[test]
+	fn test_to_mathml() {
+		assert_eq!(to_mathml("x^2", false).unwrap(), "<math><mrow><msup><mi>x</mi><mn>2</mn></msup></mrow></math>");
+		assert_eq!(
+			to_mathml("\\frac{a}{b}", true).unwrap(),
+			"<math display=\"block\"><mrow><mfrac><mrow><mi>a</mi></mrow><mrow><mi>b</mi></mrow></mfrac></mrow></math>"
+		);
+		assert_eq!(to_mathml("\\alpha_i", false).unwrap(), "<math><mrow><msub><mi>α</mi><mi>i</mi></msub></mrow></math>");
+		assert_eq!(
+			to_mathml("\\sqrt[3]{x}", false).unwrap(),
+			"<math><mrow><mroot><mrow><mi>x</mi></mrow><mrow><mn>3</mn></mrow></mroot></mrow></math>"
+		);
+		assert_eq!(
+			to_mathml("\\left( x \\right)", false).unwrap(),
+			"<math><mrow><mrow><mo>(</mo><mi>x</mi><mo>)</mo></mrow></mrow></math>"
+		);
+		assert!(to_mathml("\\unknowncommand", false).is_none());
+		assert!(to_mathml("{x", false).is_none());
+	}
+
+	#[test]
+	fn test_to_mathml_depth() {
+		let nested = format!("{}x{}", "{".repeat(20), "}".repeat(20));
+		assert!(to_mathml(&nested, false).is_some());
+
+		// Deep nesting is refused rather than recursed into
+		let braces = format!("{}x{}", "{".repeat(50_000), "}".repeat(50_000));
+		assert!(to_mathml(&braces, false).is_none());
+		let powers = format!("{}{}", "x^{".repeat(50_000), "}".repeat(50_000));
+		assert!(to_mathml(&powers, false).is_none());
+		let fractions = "\\frac{".repeat(50_000);
+		assert!(to_mathml(&fractions, false).is_none());
+		let body = format!("<p>${}$</p>", "{".repeat(50_000));
+		assert_eq!(render(&body), body);
+	}
+
+	#[test]
+	fn test_render() {
+		assert_eq!(
+			render("<p>So $x^2$ it is</p>"),
+			"<p>So <math><mrow><msup><mi>x</mi><mn>2</mn></msup></mrow></math> it is</p>"
+		);
+		// Prices aren't math
+		assert_eq!(render("<p>It costs $5 or $10</p>"), "<p>It costs $5 or $10</p>");
+		// Neither is anything in code
+		assert_eq!(render("<p><code>$x$</code></p>"), "<p><code>$x$</code></p>");
+		// Unknown commands are left as written
+		assert_eq!(render("<p>$\\foo$</p>"), "<p>$\\foo$</p>");
+	}
+}
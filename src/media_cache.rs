@@ -0,0 +1,200 @@
+// In-memory cache of media proxied from Reddit's CDNs, so thumbnails and
+// emoji that many visitors see are only fetched once.
+
+use hyper::body::Bytes;
+use hyper::{header, HeaderMap, Response, StatusCode};
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Bytes of media the cache may hold, set through `--media-cache-size`.
+/// Nothing is cached when unset or zero.
+pub static CAPACITY: OnceCell<usize> = OnceCell::new();
+
+/// Share of the cache a single file may take up, so one large file can't
+/// push out everything else. Larger files are streamed without caching.
+const MAX_ENTRY_SHARE: usize = 16;
+
+static CACHE: Lazy<Mutex<MediaCache>> = Lazy::new(|| Mutex::new(MediaCache::new(CAPACITY.get().copied().unwrap_or_default())));
+
+struct Entry {
+	status: StatusCode,
+	headers: HeaderMap,
+	body: Bytes,
+	expires: Instant,
+	/// Position in the least-recently-used order
+	used: u64,
+}
+
+/// Size-bounded cache of media responses, evicting the least recently used.
+pub struct MediaCache {
+	capacity: usize,
+	size: usize,
+	clock: u64,
+	entries: HashMap<String, Entry>,
+	/// URLs by when they were last used, oldest first
+	order: BTreeMap<u64, String>,
+}
+
+impl MediaCache {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			size: 0,
+			clock: 0,
+			entries: HashMap::new(),
+			order: BTreeMap::new(),
+		}
+	}
+
+	/// Largest file worth caching
+	pub fn max_entry(&self) -> usize {
+		self.capacity / MAX_ENTRY_SHARE
+	}
+
+	/// Returns the cached response for `url`, unless it has expired.
+	pub fn get(&mut self, url: &str, now: Instant) -> Option<Response<hyper::Body>> {
+		if self.entries.get(url)?.expires <= now {
+			self.remove(url);
+			return None;
+		}
+
+		self.clock += 1;
+		let entry = self.entries.get_mut(url)?;
+		self.order.remove(&entry.used);
+		entry.used = self.clock;
+		self.order.insert(self.clock, url.to_string());
+
+		let mut res = Response::new(hyper::Body::from(entry.body.clone()));
+		*res.status_mut() = entry.status;
+		*res.headers_mut() = entry.headers.clone();
+		Some(res)
+	}
+
+	/// Caches the response for `url` for `ttl`, making room by evicting the
+	/// least recently used files.
+	pub fn insert(&mut self, url: &str, status: StatusCode, headers: HeaderMap, body: Bytes, ttl: Duration, now: Instant) {
+		if body.len() > self.max_entry() {
+			return;
+		}
+
+		self.remove(url);
+		while self.size + body.len() > self.capacity {
+			let Some((_, oldest)) = self.order.pop_first() else {
+				break;
+			};
+			if let Some(entry) = self.entries.remove(&oldest) {
+				self.size -= entry.body.len();
+			}
+		}
+
+		self.clock += 1;
+		self.size += body.len();
+		self.order.insert(self.clock, url.to_string());
+		self.entries.insert(
+			url.to_string(),
+			Entry {
+				status,
+				headers,
+				body,
+				expires: now + ttl,
+				used: self.clock,
+			},
+		);
+	}
+
+	fn remove(&mut self, url: &str) {
+		if let Some(entry) = self.entries.remove(url) {
+			self.order.remove(&entry.used);
+			self.size -= entry.body.len();
+		}
+	}
+}
+
+/// Whether media is being cached at all
+pub fn enabled() -> bool {
+	CAPACITY.get().is_some_and(|&capacity| capacity > 0)
+}
+
+/// Largest file worth caching, to decide whether to buffer a response
+pub fn max_entry() -> usize {
+	CACHE.lock().unwrap_or_else(PoisonError::into_inner).max_entry()
+}
+
+pub fn get(url: &str) -> Option<Response<hyper::Body>> {
+	CACHE.lock().unwrap_or_else(PoisonError::into_inner).get(url, Instant::now())
+}
+
+pub fn insert(url: &str, status: StatusCode, headers: HeaderMap, body: Bytes, ttl: Duration) {
+	CACHE.lock().unwrap_or_else(PoisonError::into_inner).insert(url, status, headers, body, ttl, Instant::now());
+}
+
+/// How long Reddit allows a response to be kept, from its `Cache-Control`
+/// header. Responses that mustn't be stored, or say nothing, aren't cached.
+pub fn ttl(headers: &HeaderMap) -> Option<Duration> {
+	let cache_control = headers.get(header::CACHE_CONTROL)?.to_str().ok()?.to_ascii_lowercase();
+	let directives: Vec<&str> = cache_control.split(',').map(str::trim).collect();
+
+	if directives.iter().any(|directive| matches!(*directive, "no-store" | "no-cache" | "private")) {
+		return None;
+	}
+
+	let max_age = directives
+		.iter()
+		.find_map(|directive| directive.strip_prefix("s-maxage="))
+		.or_else(|| directives.iter().find_map(|directive| directive.strip_prefix("max-age=")))?;
+	max_age.parse::<u64>().ok().filter(|&seconds| seconds > 0).map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ttl, MediaCache};
+	use hyper::body::Bytes;
+	use hyper::{header, HeaderMap, StatusCode};
+	use std::time::{Duration, Instant};
+
+	fn insert(cache: &mut MediaCache, url: &str, size: usize, now: Instant) {
+		cache.insert(url, StatusCode::OK, HeaderMap::new(), Bytes::from(vec![0; size]), Duration::from_secs(60), now);
+	}
+
+	#[test]
+	fn test_lru_eviction() {
+		let now = Instant::now();
+		let mut cache = MediaCache::new(1600);
+
+		insert(&mut cache, "a", 100, now);
+		insert(&mut cache, "b", 100, now);
+		assert!(cache.get("a", now).is_some());
+
+		// Filling the cache evicts b, which was used longest ago
+		for i in 0..15 {
+			insert(&mut cache, &i.to_string(), 100, now);
+		}
+		assert!(cache.get("a", now).is_some());
+		assert!(cache.get("b", now).is_none());
+		assert!(cache.size <= 1600);
+
+		// Too big for the cache
+		insert(&mut cache, "big", 101, now);
+		assert!(cache.get("big", now).is_none());
+
+		assert!(cache.get("a", now + Duration::from_secs(61)).is_none());
+	}
+
+	#[test]
+	fn test_ttl() {
+		let headers = |value: &'static str| {
+			let mut headers = HeaderMap::new();
+			headers.insert(header::CACHE_CONTROL, header::HeaderValue::from_static(value));
+			headers
+		};
+
+		assert_eq!(ttl(&headers("public, max-age=3600")), Some(Duration::from_secs(3600)));
+		assert_eq!(ttl(&headers("max-age=3600, s-maxage=60")), Some(Duration::from_secs(60)));
+		assert_eq!(ttl(&headers("private, max-age=3600")), None);
+		assert_eq!(ttl(&headers("no-store")), None);
+		assert_eq!(ttl(&headers("max-age=0")), None);
+		assert_eq!(ttl(&HeaderMap::new()), None);
+	}
+}
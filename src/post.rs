@@ -1,17 +1,19 @@
 // CRATES
 use crate::client::json;
 use crate::config::get_setting;
+use crate::math;
 use crate::server::RequestExt;
 use crate::subreddit::{can_access_quarantine, quarantine};
 use crate::utils::{
-	error, format_num, get_filters, nsfw_landing, param, parse_post, rewrite_urls, setting, template, time, val, Author, Awards, Comment, Flair, FlairPart, Post, Preferences,
+	cache, debug_json, debug_response, error, format_num, get_filters, highlight_matches, html_contains, nsfw_landing, param, parse_post, rebase_html, rewrite_urls, setting,
+	sfw_only, template, time, val, Author, Awards, CachePolicy, Comment, Flair, FlairPart, Post, Preferences, HTML_CONTENT_TYPE,
 };
 use hyper::{Body, Request, Response};
 
 use askama::Template;
-use once_cell::sync::Lazy;
-use regex::Regex;
-use std::collections::HashSet;
+use once_cell::sync::OnceCell;
+use serde_json::{json as json_value, Value};
+use std::collections::{HashMap, HashSet};
 
 // STRUCTS
 #[derive(Template)]
@@ -22,16 +24,67 @@ struct PostTemplate {
 	sort: String,
 	prefs: Preferences,
 	single_thread: bool,
+	/// ID of the comment a permalink points to, which stays in view when
+	/// re-sorting
+	focused: String,
+	/// Levels of parent comments shown above `focused`, from `?context=`
+	context: u32,
 	url: String,
 	url_without_query: String,
 	comment_query: String,
+	/// Whether only awarded comments are shown, with `?gilded=1`
+	gilded: bool,
+	/// IDs of the comments matching `comment_query` or `gilded`, in thread order
+	matches: Vec<String>,
+	/// Whether all comments were requested with `?limit=all`
+	expanded: bool,
+	/// Links from the subreddit down to the current page, as (label, href)
+	breadcrumbs: Vec<(String, String)>,
+	/// Query string loading more top-level comments, if Reddit left some out
+	load_more: String,
+	/// Query strings of the previous and next pages of top-level comments
+	comment_pages: (String, String),
+	/// Whether the comments are left out of the template and streamed in
+	/// afterwards by `stream_page`
+	streamed: bool,
 }
 
-static COMMENT_SEARCH_CAPTURE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\?q=(.*)&type=comment"#).unwrap());
+// Stands in for the comments when they're streamed
+pub const COMMENTS_PLACEHOLDER: &str = "<!-- libreddit:comments -->";
+
+// Threads with more top-level comments than this are streamed to the client
+// as they're rendered instead of being rendered in one go
+const STREAM_THRESHOLD: usize = 100;
+
+// Longest post title shown in the breadcrumbs
+const BREADCRUMB_TITLE_LENGTH: usize = 60;
+
+// Most comments Reddit returns for a post in one request
+const MAX_COMMENT_LIMIT: u32 = 500;
+
+// Number of "more" comments fetched per request, and the most requests made
+// when expanding a thread. Keeps huge threads from taking forever (or all of
+// our memory).
+const MORE_BATCH_SIZE: usize = 100;
+const MAX_MORE_BATCHES: usize = 5;
+
+// Parent comments shown above the comment a permalink points to, unless
+// `?context=` asks for more or fewer. Reddit won't go further than the maximum.
+const DEFAULT_COMMENT_CONTEXT: u32 = 1;
+pub const MAX_COMMENT_CONTEXT: u32 = 8;
+
+// Deepest level of replies built into the comment tree, set with
+// `--max-comment-depth`. Anything deeper is left behind a link to its thread.
+pub static MAX_COMMENT_DEPTH: OnceCell<usize> = OnceCell::new();
 
 pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
-	// Build Reddit API path
-	let mut path: String = format!("{}.json?{}&raw_json=1", req.uri().path(), req.uri().query().unwrap_or_default());
+	// Build Reddit API path. Posts on a user's profile are filed under their
+	// u_ subreddit, where Reddit reliably serves them.
+	let post_path = match (req.param("name"), req.uri().path().split_once("/comments/")) {
+		(Some(name), Some((_, rest))) => format!("/r/u_{}/comments/{}", name, rest),
+		_ => req.uri().path().to_string(),
+	};
+	let mut path: String = format!("{}.json?{}&raw_json=1", post_path, req.uri().query().unwrap_or_default());
 	let sub = req.param("sub").unwrap_or_default();
 	let quarantined = can_access_quarantine(&req, &sub);
 	let url = req.uri().to_string();
@@ -45,7 +98,7 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 		if default_sort.is_empty() {
 			String::new()
 		} else {
-			path = format!("{}.json?{}&sort={}&raw_json=1", req.uri().path(), req.uri().query().unwrap_or_default(), default_sort);
+			path = format!("{}.json?{}&sort={}&raw_json=1", post_path, req.uri().query().unwrap_or_default(), default_sort);
 			default_sort
 		}
 	});
@@ -55,14 +108,85 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 	dbg!(req.param("id").unwrap_or_default());
 
 	let single_thread = req.param("comment_id").is_some();
+	let gilded = param(&path, "gilded").is_some_and(|gilded| gilded == "1");
+
+	// Show the parents of the comment a permalink points to
+	let requested_context = param(&path, "context").filter(|_| single_thread);
+	let context = match &requested_context {
+		Some(context) => context.parse::<u32>().map_or(DEFAULT_COMMENT_CONTEXT, |context| context.min(MAX_COMMENT_CONTEXT)),
+		None => DEFAULT_COMMENT_CONTEXT,
+	};
+	if single_thread {
+		path = match &requested_context {
+			Some(requested) => path.replace(&format!("context={}", requested), &format!("context={}", context)),
+			None => format!("{}&context={}", path, context),
+		};
+	}
 	let highlighted_comment = &req.param("comment_id").unwrap_or_default();
 
+	// Ask for as many comments as Reddit allows when expanding the thread
+	let expanded = param(&path, "limit").is_some_and(|limit| limit == "all");
+	if expanded {
+		path = path.replace("limit=all", &format!("limit={}&depth=10", MAX_COMMENT_LIMIT));
+	}
+
+	// Otherwise cap the number of comments at the requested or preferred limit
+	let requested_limit = param(&path, "limit").filter(|_| !expanded);
+	let comment_limit = match &requested_limit {
+		Some(limit) => limit.parse::<u32>().ok(),
+		None => setting(&req, "comment_limit").parse::<u32>().ok(),
+	}
+	.map(|limit| limit.clamp(1, MAX_COMMENT_LIMIT));
+
+	if let Some(limit) = comment_limit {
+		path = match &requested_limit {
+			Some(requested) => path.replace(&format!("limit={}", requested), &format!("limit={}", limit)),
+			None => format!("{}&limit={}", path, limit),
+		};
+	}
+
+	if debug_json(&req) {
+		return debug_response(&path, quarantined).await;
+	}
+
 	// Send a request to the url, receive JSON in response
-	match json(path, quarantined).await {
+	match json(path.clone(), quarantined).await {
 		// Otherwise, grab the JSON output from the request
-		Ok(response) => {
+		Ok(mut response) => {
 			// Parse the JSON into Post and Comment structs
-			let post = parse_post(&response[0]["data"]["children"][0]).await;
+			let mut post = parse_post(&response[0]["data"]["children"][0]).await;
+			if setting(&req, "render_math") == "on" {
+				post.body = math::render(&post.body);
+			}
+
+			// Reddit serves contest mode threads in random order whatever sort is
+			// asked for, so don't claim another one
+			let sort = if post.contest_mode { String::new() } else { sort };
+
+			if expanded {
+				expand_comments(&mut response[1], &post.id, &sort, quarantined).await;
+			}
+
+			if setting(&req, "inline_threads") == "on" {
+				expand_threads(&mut response[1], &post.id, &sort, quarantined).await;
+			}
+
+			// Page through the top-level comments Reddit left out of the first batch
+			let comment_pages = if expanded || single_thread || gilded || param(&url, "q").is_some() {
+				(String::new(), String::new())
+			} else {
+				let cursor = match (param(&url, "before"), param(&url, "after")) {
+					(_, Some(after)) => Some(Cursor::After(after)),
+					(Some(before), None) => Some(Cursor::Before(before)),
+					(None, None) => None,
+				};
+				let (before, after) = page_comments(&mut response[1], &post.id, &sort, cursor, quarantined).await;
+				let sort_query = if sort.is_empty() { String::new() } else { format!("&sort={}", sort) };
+				(
+					before.map_or(String::new(), |id| format!("?before={}{}", id, sort_query)),
+					after.map_or(String::new(), |id| format!("?after={}{}", id, sort_query)),
+				)
+			};
 
 			let req_url = req.uri().to_string();
 			// Return landing page if this post if this Reddit deems this post
@@ -72,89 +196,595 @@ pub async fn item(req: Request<Body>) -> Result<Response<Body>, String> {
 				return Ok(nsfw_landing(req, req_url).await.unwrap_or_default());
 			}
 
-			let query = match COMMENT_SEARCH_CAPTURE.captures(&url) {
-				Some(captures) => captures.get(1).unwrap().as_str().replace("%20", " ").replace('+', " "),
-				None => String::new(),
+			let query = param(&url, "q").map(|query| query.trim().to_string()).unwrap_or_default();
+
+			let filter = match query.as_str() {
+				"" if gilded => Some(CommentFilter::Gilded),
+				"" => None,
+				_ => Some(CommentFilter::Query(&query)),
 			};
+			let mut comments = match &filter {
+				None => parse_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &req, 1),
+				Some(filter) => filter_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), filter, &req, 1),
+			};
+
+			if post.contest_mode || setting(&req, "hide_comment_scores") == "on" {
+				hide_scores(&mut comments);
+			}
 
-			let comments = match query.as_str() {
-				"" => parse_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &req),
-				_ => query_comments(&response[1], &post.permalink, &post.author.name, highlighted_comment, &get_filters(&req), &query, &req),
+			let matches = link_matches(&mut comments);
+
+			// Offer to load more when Reddit cut the top-level comments short,
+			// unless they can be paged through instead
+			let paged = !comment_pages.0.is_empty() || !comment_pages.1.is_empty();
+			let load_more = if !expanded && !single_thread && !paged && comments.iter().any(|c| c.kind == "more" && c.parent_kind == "t3") {
+				let limit = match comment_limit {
+					Some(limit) if limit < MAX_COMMENT_LIMIT => (limit * 2).min(MAX_COMMENT_LIMIT).to_string(),
+					_ => "all".to_string(),
+				};
+				if sort.is_empty() {
+					format!("?limit={}", limit)
+				} else {
+					format!("?limit={}&sort={}", limit, sort)
+				}
+			} else {
+				String::new()
 			};
 
+			let breadcrumbs = breadcrumbs(&post, single_thread.then(|| comments.first()).flatten(), req.uri().path());
+
+			// Hold huge comment trees back from the template to stream them
+			let streamed = !single_thread && (expanded || comments.len() > STREAM_THRESHOLD);
+			let (comments, streamed_comments) = if streamed { (Vec::new(), comments) } else { (comments, Vec::new()) };
+
 			// Use the Post and Comment structs to generate a website to show users
-			template(PostTemplate {
+			let page = PostTemplate {
 				comments,
 				post,
-				url_without_query: url.clone().trim_end_matches(&format!("?q={query}&type=comment")).to_string(),
+				url_without_query: req.uri().path().to_string(),
 				sort,
 				prefs: Preferences::new(&req),
 				single_thread,
+				focused: highlighted_comment.to_string(),
+				context,
 				url: req_url,
 				comment_query: query,
-			})
+				gilded,
+				matches,
+				expanded,
+				breadcrumbs,
+				load_more,
+				comment_pages,
+				streamed,
+			};
+
+			if streamed {
+				cache(Ok(stream_page(&rebase_html(page.render().unwrap_or_default()), streamed_comments)), CachePolicy::Post)
+			} else {
+				cache(template(page), CachePolicy::Post)
+			}
 		}
 		// If the Reddit API returns an error, exit and send error page to user
-		Err(msg) => {
-			if msg == "quarantined" || msg == "gated" {
+		Err(e) => {
+			if e.msg == "quarantined" || e.msg == "gated" || (e.msg == "over18" && !sfw_only()) {
 				let sub = req.param("sub").unwrap_or_default();
-				quarantine(req, sub, msg)
+				quarantine(req, sub, e.msg)
+			} else if e.msg == "over18" {
+				let req_url = req.uri().to_string();
+				nsfw_landing(req, req_url).await
 			} else {
-				error(req, msg).await
+				error(req, e).await
 			}
 		}
 	}
 }
 
+// Hides the scores of a comment tree, as Reddit does in contest mode and
+// readers can ask for to not be swayed by them
+fn hide_scores(comments: &mut [Comment]) {
+	for comment in comments {
+		comment.score = ("\u{2022}".to_string(), "Hidden".to_string());
+		comment.controversial = false;
+		comment.votes = String::new();
+		hide_scores(&mut comment.replies);
+	}
+}
+
+// Sends the page around the comments right away, then renders and sends the
+// comments one thread at a time. The comment tree is still parsed in full
+// first, but its HTML is never held all at once, and the browser can start on
+// the page before the last thread is rendered. The server compresses the
+// stream as it goes.
+fn stream_page(page: &str, comments: Vec<Comment>) -> Response<Body> {
+	let (head, tail) = page.split_once(COMMENTS_PLACEHOLDER).unwrap_or((page, ""));
+	let (head, tail) = (head.to_string(), tail.to_string());
+	let (mut sender, body) = Body::channel();
+
+	tokio::spawn(async move {
+		if sender.send_data(head.into()).await.is_err() {
+			return;
+		}
+
+		for comment in comments {
+			let thread = rebase_html(format!(r#"<div class="thread">{}</div>"#, comment.render().unwrap_or_default()));
+			// The client went away
+			if sender.send_data(thread.into()).await.is_err() {
+				return;
+			}
+		}
+
+		let _ = sender.send_data(tail.into()).await;
+	});
+
+	Response::builder().status(200).header("content-type", HTML_CONTENT_TYPE).body(body).unwrap_or_default()
+}
+
+// Builds the breadcrumbs for `post`, ending in the single `comment` thread
+// being viewed at `path`, if any
+fn breadcrumbs(post: &Post, comment: Option<&Comment>, path: &str) -> Vec<(String, String)> {
+	let mut crumbs = vec![match post.community.strip_prefix("u_") {
+		Some(user) => (format!("u/{}", user), format!("/user/{}", user)),
+		None => (format!("r/{}", post.community), format!("/r/{}", post.community)),
+	}];
+
+	let title = if post.title.chars().count() > BREADCRUMB_TITLE_LENGTH {
+		format!("{}…", post.title.chars().take(BREADCRUMB_TITLE_LENGTH).collect::<String>().trim_end())
+	} else {
+		post.title.clone()
+	};
+	crumbs.push((title, post.permalink.clone()));
+
+	if let Some(comment) = comment {
+		crumbs.push((format!("comment by u/{}", comment.author.name), path.to_string()));
+	}
+
+	crumbs
+}
+
 // COMMENTS
 
-fn parse_comments(json: &serde_json::Value, post_link: &str, post_author: &str, highlighted_comment: &str, filters: &HashSet<String>, req: &Request<Body>) -> Vec<Comment> {
+/// Replaces the "more" stubs in a comment listing with the comments they stand
+/// for, requested from Reddit in batches.
+async fn expand_comments(listing: &mut Value, post_id: &str, sort: &str, quarantined: bool) {
+	let mut ids = Vec::new();
+	take_more_stubs(listing, &mut ids, MORE_BATCH_SIZE * MAX_MORE_BATCHES);
+
+	for batch in ids.chunks(MORE_BATCH_SIZE) {
+		if !load_children(listing, post_id, batch, sort, quarantined).await {
+			break;
+		}
+	}
+}
+
+/// Fetches the comments with the given `ids` and adds them to `listing`:
+/// top-level comments at the end, replies under their parents. Returns false
+/// if Reddit couldn't be asked for them.
+async fn load_children(listing: &mut Value, post_id: &str, ids: &[String], sort: &str, quarantined: bool) -> bool {
+	let path = format!(
+		"/api/morechildren.json?api_type=json&link_id=t3_{}&children={}{}&raw_json=1",
+		post_id,
+		ids.join(","),
+		if sort.is_empty() { String::new() } else { format!("&sort={}", sort) }
+	);
+
+	let Ok(res) = json(path, quarantined).await else {
+		return false;
+	};
+
+	// Comments come back as a flat list, with parents before their replies
+	for thing in res["json"]["data"]["things"].as_array().into_iter().flatten() {
+		let parent_id = thing["data"]["parent_id"].as_str().unwrap_or_default();
+		if parent_id.starts_with("t3_") {
+			if let Some(children) = listing["data"]["children"].as_array_mut() {
+				children.push(thing.clone());
+			}
+		} else {
+			insert_reply(listing, parent_id, thing);
+		}
+	}
+
+	true
+}
+
+/// Replaces the "continue this thread" stubs Reddit leaves where threads get
+/// too deep with the replies they stand for. Each thread takes a request, so
+/// only the first few are expanded; the rest keep their link.
+async fn expand_threads(listing: &mut Value, post_id: &str, sort: &str, quarantined: bool) {
+	let mut parents = Vec::new();
+	find_thread_stubs(listing, &mut parents);
+
+	for parent in parents.iter().take(MAX_MORE_BATCHES) {
+		let path = format!(
+			"/comments/{}/_/{}.json?raw_json=1{}",
+			post_id,
+			parent.trim_start_matches("t1_"),
+			if sort.is_empty() { String::new() } else { format!("&sort={}", sort) }
+		);
+
+		let Ok(res) = json(path, quarantined).await else {
+			break;
+		};
+
+		let replies = &res[1]["data"]["children"][0]["data"]["replies"]["data"]["children"];
+		if let Some(replies) = replies.as_array().filter(|replies| !replies.is_empty()) {
+			replace_thread_stub(listing, parent, replies);
+		}
+	}
+}
+
+// Whether `child` is a "continue this thread" stub: a "more" without any
+// comment IDs to load, hanging off a comment
+fn is_thread_stub(child: &Value) -> bool {
+	child["kind"] == "more"
+		&& child["data"]["children"].as_array().is_none_or(Vec::is_empty)
+		&& child["data"]["parent_id"].as_str().is_some_and(|parent| parent.starts_with("t1_"))
+}
+
+// Collects the full names of the comments whose replies were cut off
+fn find_thread_stubs(listing: &Value, parents: &mut Vec<String>) {
+	for child in listing["data"]["children"].as_array().into_iter().flatten() {
+		if is_thread_stub(child) {
+			parents.extend(child["data"]["parent_id"].as_str().map(String::from));
+		} else if child["data"]["replies"].is_object() {
+			find_thread_stubs(&child["data"]["replies"], parents);
+		}
+	}
+}
+
+// Swaps the stub under the comment named `parent` for its `replies`
+fn replace_thread_stub(listing: &mut Value, parent: &str, replies: &[Value]) -> bool {
+	let Some(children) = listing["data"]["children"].as_array_mut() else {
+		return false;
+	};
+
+	for child in children.iter_mut() {
+		if !child["data"]["replies"].is_object() {
+			continue;
+		}
+
+		if child["data"]["name"] == parent {
+			if let Some(own) = child["data"]["replies"]["data"]["children"].as_array_mut() {
+				own.retain(|reply| !is_thread_stub(reply));
+				own.extend(replies.iter().cloned());
+			}
+			return true;
+		}
+
+		if replace_thread_stub(&mut child["data"]["replies"], parent, replies) {
+			return true;
+		}
+	}
+
+	false
+}
+
+// Where a page of top-level comments starts or ends
+enum Cursor {
+	/// The page ends right before this comment
+	Before(String),
+	/// The page starts right after this comment
+	After(String),
+}
+
+// Replaces the top-level comments of `listing` with the page at `cursor`,
+// fetching the comments Reddit left out of the first batch as needed.
+// Returns the IDs to page back from and forward from, if there are pages
+// before and after this one.
+async fn page_comments(listing: &mut Value, post_id: &str, sort: &str, cursor: Option<Cursor>, quarantined: bool) -> (Option<String>, Option<String>) {
+	let children = listing["data"]["children"].as_array().cloned().unwrap_or_default();
+	let loaded: Vec<&Value> = children.iter().filter(|child| child["kind"] == "t1").collect();
+
+	// All top-level comment IDs in order: the ones loaded, then the ones in the
+	// top-level "more" stub
+	let mut ids: Vec<String> = loaded.iter().map(|child| child["data"]["id"].as_str().unwrap_or_default().to_string()).collect();
+	for stub in children
+		.iter()
+		.filter(|child| child["kind"] == "more" && child["data"]["parent_id"] == format!("t3_{}", post_id))
+	{
+		ids.extend(stub["data"]["children"].as_array().into_iter().flatten().filter_map(|id| id.as_str().map(String::from)));
+	}
+
+	let position = |id: &str| ids.iter().position(|other| other == id);
+	let range = match &cursor {
+		Some(Cursor::After(id)) => position(id).map(|i| i + 1..(i + 1 + MORE_BATCH_SIZE).min(ids.len())),
+		Some(Cursor::Before(id)) => position(id).map(|i| i.saturating_sub(MORE_BATCH_SIZE)..i),
+		None => None,
+	};
+
+	// The first page is the batch Reddit returned
+	let Some(range) = range else {
+		let next = (ids.len() > loaded.len()).then(|| loaded.len().checked_sub(1).map(|last| ids[last].clone())).flatten();
+		return (None, next);
+	};
+
+	// Keep the comments on this page that are already loaded, and fetch the rest
+	let kept: Vec<Value> = range.clone().filter(|&i| i < loaded.len()).map(|i| loaded[i].clone()).collect();
+	let missing: Vec<String> = range.clone().filter(|&i| i >= loaded.len()).map(|i| ids[i].clone()).collect();
+	let mut page = json_value!({ "kind": "Listing", "data": { "children": kept } });
+
+	if !missing.is_empty() {
+		load_children(&mut page, post_id, &missing, sort, quarantined).await;
+	}
+
+	*listing = page;
+
+	let before = (range.start > 0).then(|| ids[range.start].clone());
+	let after = (range.end < ids.len()).then(|| ids[range.end - 1].clone());
+	(before, after)
+}
+
+// Remove "more" stubs from a listing, collecting the IDs of the comments they
+// hide, until `max` IDs have been collected.
+fn take_more_stubs(listing: &mut Value, ids: &mut Vec<String>, max: usize) {
+	let Some(children) = listing["data"]["children"].as_array_mut() else {
+		return;
+	};
+
+	children.retain(|child| {
+		if child["kind"] != "more" {
+			return true;
+		}
+
+		// Stubs without children link to deeper threads, keep those
+		let stub: Vec<String> = child["data"]["children"]
+			.as_array()
+			.into_iter()
+			.flatten()
+			.filter_map(|id| id.as_str().map(String::from))
+			.collect();
+		if stub.is_empty() || ids.len() + stub.len() > max {
+			return true;
+		}
+
+		ids.extend(stub);
+		false
+	});
+
+	for child in children.iter_mut() {
+		if child["data"]["replies"].is_object() {
+			take_more_stubs(&mut child["data"]["replies"], ids, max);
+		}
+	}
+}
+
+// Add `reply` to the replies of the comment with fullname `parent_id`
+fn insert_reply(listing: &mut Value, parent_id: &str, reply: &Value) -> bool {
+	let Some(children) = listing["data"]["children"].as_array_mut() else {
+		return false;
+	};
+
+	for child in children.iter_mut() {
+		if child["data"]["name"] == parent_id {
+			if !child["data"]["replies"].is_object() {
+				child["data"]["replies"] = json_value!({ "kind": "Listing", "data": { "children": [] } });
+			}
+			if let Some(replies) = child["data"]["replies"]["data"]["children"].as_array_mut() {
+				replies.push(reply.clone());
+			}
+			return true;
+		}
+
+		if child["data"]["replies"].is_object() && insert_reply(&mut child["data"]["replies"], parent_id, reply) {
+			return true;
+		}
+	}
+
+	false
+}
+
+fn parse_comments(
+	json: &serde_json::Value,
+	post_link: &str,
+	post_author: &str,
+	highlighted_comment: &str,
+	filters: &HashSet<String>,
+	req: &Request<Body>,
+	depth: usize,
+) -> Vec<Comment> {
 	// Parse the comment JSON into a Vector of Comments
 	let comments = json["data"]["children"].as_array().map_or(Vec::new(), std::borrow::ToOwned::to_owned);
+	let cut_off = MAX_COMMENT_DEPTH.get().is_some_and(|max| depth >= *max);
 
 	// For each comment, retrieve the values to build a Comment object
 	comments
 		.into_iter()
 		.map(|comment| {
 			let data = &comment["data"];
-			let replies: Vec<Comment> = if data["replies"].is_object() {
-				parse_comments(&data["replies"], post_link, post_author, highlighted_comment, filters, req)
+			let has_replies = data["replies"].is_object();
+			let replies: Vec<Comment> = if has_replies && !cut_off {
+				parse_comments(&data["replies"], post_link, post_author, highlighted_comment, filters, req, depth + 1)
 			} else {
 				Vec::new()
 			};
-			build_comment(&comment, data, replies, post_link, post_author, highlighted_comment, filters, req)
+			let mut c = build_comment(&comment, data, replies, post_link, post_author, highlighted_comment, filters, req);
+			c.continue_thread = has_replies && cut_off;
+			c
 		})
 		.collect()
 }
 
-fn query_comments(
+// Which comments `filter_comments` keeps
+enum CommentFilter<'a> {
+	// Comments containing the search query, from `?q=`
+	Query(&'a str),
+	// Loaded comments that received awards, from `?gilded=1`. Reddit has no
+	// listing of a post's awarded comments, so ones in "more" stubs are missed
+	Gilded,
+}
+
+impl CommentFilter<'_> {
+	fn matches(&self, data: &serde_json::Value) -> bool {
+		match self {
+			Self::Query(query) => html_contains(data["body_html"].as_str().unwrap_or_default(), query),
+			Self::Gilded => data["gilded"].as_i64().unwrap_or_default() > 0 || data["all_awardings"].as_array().is_some_and(|awards| !awards.is_empty()),
+		}
+	}
+}
+
+// Keeps the comments matching `filter`, highlighted, along with the comments
+// they reply to for context. Replies past the maximum depth aren't searched,
+// as in `parse_comments`.
+#[allow(clippy::too_many_arguments)]
+fn filter_comments(
 	json: &serde_json::Value,
 	post_link: &str,
 	post_author: &str,
 	highlighted_comment: &str,
 	filters: &HashSet<String>,
-	query: &str,
+	filter: &CommentFilter,
 	req: &Request<Body>,
+	depth: usize,
 ) -> Vec<Comment> {
 	let comments = json["data"]["children"].as_array().map_or(Vec::new(), std::borrow::ToOwned::to_owned);
-	let mut results = Vec::new();
+	let cut_off = MAX_COMMENT_DEPTH.get().is_some_and(|max| depth >= *max);
 
-	comments.into_iter().for_each(|comment| {
-		let data = &comment["data"];
+	comments
+		.into_iter()
+		.filter_map(|comment| {
+			let data = &comment["data"];
+
+			// If this comment contains replies, search those too
+			let has_replies = data["replies"].is_object();
+			let replies = if has_replies && !cut_off {
+				filter_comments(&data["replies"], post_link, post_author, highlighted_comment, filters, filter, req, depth + 1)
+			} else {
+				Vec::new()
+			};
+
+			let matched = comment["kind"] == "t1" && filter.matches(data);
+			if !matched && replies.is_empty() {
+				return None;
+			}
+
+			let mut c = build_comment(&comment, data, replies, post_link, post_author, highlighted_comment, filters, req);
+			c.continue_thread = has_replies && cut_off;
+			if matched {
+				if let CommentFilter::Query(query) = filter {
+					c.body = highlight_matches(&c.body, query);
+				}
+				c.matched = true;
+			}
+			// Don't hide matches inside collapsed comments
+			c.collapsed = c.collapsed && !c.is_filtered && !matched;
+			Some(c)
+		})
+		.collect()
+}
 
-		// If this comment contains replies, handle those too
-		if data["replies"].is_object() {
-			results.append(&mut query_comments(&data["replies"], post_link, post_author, highlighted_comment, filters, query, req))
+// Returns the IDs of the comments matching the comment search in thread
+// order, pointing each match at the next one
+fn link_matches(comments: &mut [Comment]) -> Vec<String> {
+	fn collect(comments: &[Comment], matches: &mut Vec<String>) {
+		for comment in comments {
+			if comment.matched {
+				matches.push(comment.id.clone());
+			}
+			collect(&comment.replies, matches);
 		}
+	}
 
-		let c = build_comment(&comment, data, Vec::new(), post_link, post_author, highlighted_comment, filters, req);
-		if c.body.to_lowercase().contains(&query.to_lowercase()) {
-			results.push(c);
+	fn link(comments: &mut [Comment], next: &HashMap<&str, &str>) {
+		for comment in comments {
+			if let Some(next_match) = next.get(comment.id.as_str()) {
+				comment.next_match = (*next_match).to_string();
+			}
+			link(&mut comment.replies, next);
 		}
-	});
+	}
+
+	let mut matches = Vec::new();
+	collect(comments, &mut matches);
+	let next: HashMap<&str, &str> = matches.windows(2).map(|pair| (pair[0].as_str(), pair[1].as_str())).collect();
+	link(comments, &next);
+
+	matches
+}
+
+// Quotes longer than this many lines are collapsed with the
+// `collapse_long_quotes` setting
+const LONG_QUOTE_LINES: usize = 8;
+
+// Characters of text counted as one line of a quote
+const QUOTE_LINE_LENGTH: usize = 80;
+
+// Puts the blockquotes in a comment's `html` that run longer than
+// `LONG_QUOTE_LINES` behind a "Show quote" expander. Quotes nested in another
+// quote go with it.
+fn collapse_long_quotes(html: &str) -> String {
+	const OPEN: &str = "<blockquote>";
+	const CLOSE: &str = "</blockquote>";
+
+	let mut collapsed = String::with_capacity(html.len());
+	let mut rest = html;
+
+	while let Some(start) = rest.find(OPEN) {
+		collapsed.push_str(&rest[..start]);
+		rest = &rest[start..];
+
+		// Find where this quote ends, past any quotes inside it
+		let mut depth = 0;
+		let mut pos = 0;
+		let mut end = None;
+		while end.is_none() {
+			let Some(close) = rest[pos..].find(CLOSE).map(|i| pos + i) else {
+				break;
+			};
+			match rest[pos..].find(OPEN).map(|i| pos + i) {
+				Some(open) if open < close => {
+					depth += 1;
+					pos = open + OPEN.len();
+				}
+				_ => {
+					depth -= 1;
+					pos = close + CLOSE.len();
+					if depth == 0 {
+						end = Some(pos);
+					}
+				}
+			}
+		}
+
+		// Leave unbalanced markup as it is
+		let Some(end) = end else {
+			break;
+		};
+
+		let quote = &rest[..end];
+		if quote_lines(quote) > LONG_QUOTE_LINES {
+			collapsed.push_str(r#"<details class="long_quote"><summary>Show quote</summary>"#);
+			collapsed.push_str(quote);
+			collapsed.push_str("</details>");
+		} else {
+			collapsed.push_str(quote);
+		}
+		rest = &rest[end..];
+	}
+
+	collapsed.push_str(rest);
+	collapsed
+}
+
+// Roughly how many lines a quote takes up, from its paragraphs, list items and
+// line breaks, and the length of its text
+fn quote_lines(quote: &str) -> usize {
+	let breaks = ["<p>", "<li>", "<br"].iter().map(|tag| quote.matches(tag).count()).sum::<usize>();
+
+	let mut in_tag = false;
+	let text = quote
+		.chars()
+		.filter(|&c| {
+			match c {
+				'<' => in_tag = true,
+				'>' => in_tag = false,
+				_ => return !in_tag,
+			}
+			false
+		})
+		.count();
 
-	results
+	breaks + text / QUOTE_LINE_LENGTH
 }
+
 #[allow(clippy::too_many_arguments)]
 fn build_comment(
 	comment: &serde_json::Value,
@@ -175,9 +805,16 @@ fn build_comment(
 			post_link,
 			id
 		)
+	} else if setting(req, "render_math") == "on" {
+		math::render(&rewrite_urls(&val(comment, "body_html")))
 	} else {
 		rewrite_urls(&val(comment, "body_html"))
 	};
+	let body = if setting(req, "collapse_long_quotes") == "on" {
+		collapse_long_quotes(&body)
+	} else {
+		body
+	};
 	let kind = comment["kind"].as_str().unwrap_or_default().to_string();
 
 	let unix_time = data["created_utc"].as_f64().unwrap_or_default();
@@ -187,6 +824,15 @@ fn build_comment(
 
 	let score = data["score"].as_i64().unwrap_or(0);
 
+	// Reddit sets controversiality to 1 for comments with many up and down votes
+	let controversial = data["controversiality"].as_i64().unwrap_or_default() > 0;
+
+	// Reddit mostly fuzzes or zeroes the down votes, so only show figures it sent
+	let votes = match (data["ups"].as_i64(), data["downs"].as_i64()) {
+		(Some(ups), Some(downs)) if downs > 0 => format!("{} up, {} down", ups, downs),
+		_ => String::new(),
+	};
+
 	// The JSON API only provides comments up to some threshold.
 	// Further comments have to be loaded by subsequent requests.
 	// The "kind" value will be "more" and the "count"
@@ -239,15 +885,41 @@ fn build_comment(
 		} else {
 			format_num(score)
 		},
+		votes,
 		rel_time,
 		created,
 		edited,
+		controversial,
 		replies,
 		highlighted,
 		awards,
 		collapsed,
 		is_filtered,
 		more_count,
+		matched: false,
+		next_match: String::new(),
+		continue_thread: false,
 		prefs: Preferences::new(req),
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::collapse_long_quotes;
+
+	#[test]
+	fn test_collapse_long_quotes() {
+		let short = "<div class=\"md\"><blockquote><p>Short</p></blockquote><p>Reply</p></div>";
+		assert_eq!(collapse_long_quotes(short), short);
+
+		let long = format!("<blockquote><p>{}</p><blockquote><p>Nested</p></blockquote></blockquote>", "word ".repeat(200));
+		assert_eq!(
+			collapse_long_quotes(&format!("<p>Before</p>{}<p>After</p>", long)),
+			format!("<p>Before</p><details class=\"long_quote\"><summary>Show quote</summary>{}</details><p>After</p>", long)
+		);
+
+		// Unbalanced quotes are left alone
+		let broken = format!("<blockquote><p>{}</p>", "word ".repeat(200));
+		assert_eq!(collapse_long_quotes(&broken), broken);
+	}
+}
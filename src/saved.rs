@@ -0,0 +1,361 @@
+// Handlers for saved and upvoted posts imported from a Reddit data export,
+// and for moving them between instances as JSON.
+
+use crate::server::{RequestExt, ResponseExt};
+use crate::utils::{error, error_with_status, filter_posts, get_filters, param, redirect, setting, template, Post, Preferences};
+
+use askama::Template;
+use cookie::Cookie;
+use futures_lite::StreamExt;
+use hyper::{header, Body, Request, Response};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use time::{Duration, OffsetDateTime};
+
+/// Lists that can be imported, as (cookie prefix, export file, route).
+pub const LISTS: [(&str, &str, &str); 2] = [("saved", "saved_posts.csv", "/saved"), ("upvoted", "post_votes.csv", "/upvoted")];
+
+/// Posts shown per page of an imported list.
+const PAGE_SIZE: usize = 25;
+
+/// Largest export file accepted, in bytes.
+const MAX_UPLOAD: usize = 10 * 1024 * 1024;
+
+/// Most bytes of post IDs a list keeps. Its cookie is sent with every
+/// request, and reverse proxies commonly reject request headers over 8KB.
+const MAX_LIST_SIZE: usize = 2048;
+
+/// Most posts Reddit returns from a single `/by_id/` request.
+const BY_ID_BATCH: usize = 100;
+
+/// ImportedTemplate renders a page of posts from an imported list.
+#[derive(Template)]
+#[template(path = "imported.html")]
+struct ImportedTemplate {
+	/// list is the name of the imported list, e.g. "saved".
+	list: String,
+
+	/// posts are the posts on this page that Reddit still has.
+	posts: Vec<Post>,
+
+	/// page is the 1-based index of the current page.
+	page: usize,
+
+	/// pages is the total number of pages.
+	pages: usize,
+
+	/// total is the number of posts in the imported list.
+	total: usize,
+
+	/// missing counts the posts on this page that Reddit no longer returns.
+	missing: usize,
+
+	/// dropped counts the posts of a fresh import that didn't fit in the list.
+	dropped: usize,
+
+	/// all_posts_filtered is true if every post on this page was filtered.
+	all_posts_filtered: bool,
+
+	prefs: Preferences,
+	url: String,
+}
+
+/// Returns the cookie prefix of the list served at `path`.
+fn list_name(path: &str) -> Option<&'static str> {
+	LISTS.iter().find(|(_, _, route)| path.trim_end_matches('/') == *route).map(|(name, _, _)| *name)
+}
+
+/// Returns the post IDs stored for `list`.
+pub fn stored_ids(req: &Request<Body>, list: &str) -> Vec<String> {
+	req
+		.cookie(list)
+		.map(|cookie| cookie.value().split('+').filter(|id| !id.is_empty()).map(ToString::to_string).collect())
+		.unwrap_or_default()
+}
+
+/// Joins as many of `ids` as fit in `MAX_LIST_SIZE` into a cookie value,
+/// returning it with the number of IDs left out.
+fn pack_ids(ids: &[String]) -> (String, usize) {
+	let mut packed = String::new();
+	for (kept, id) in ids.iter().enumerate() {
+		if packed.len() + id.len() + 1 > MAX_LIST_SIZE {
+			return (packed, ids.len() - kept);
+		}
+		if !packed.is_empty() {
+			packed.push('+');
+		}
+		packed.push_str(id);
+	}
+	(packed, 0)
+}
+
+/// Extracts the post IDs from a Reddit data export CSV. For `post_votes.csv`,
+/// only posts with a `direction` of "up" are kept. JSON exported by `export`
+/// is read too, leaving out the posts it marks as deleted.
+pub fn parse_export(csv: &str) -> Vec<String> {
+	if csv.trim_start().starts_with('{') {
+		let export: Value = serde_json::from_str(csv).unwrap_or_default();
+		let ids = export["posts"].as_array().map_or(Vec::new(), |posts| {
+			posts.iter().filter(|post| post["deleted"] != true).filter_map(|post| post["id"].as_str()).collect()
+		});
+		return valid_ids(ids.into_iter());
+	}
+
+	let mut lines = csv.lines();
+	let header: Vec<String> = lines
+		.next()
+		.unwrap_or_default()
+		.split(',')
+		.map(|column| column.trim().trim_matches('"').to_lowercase())
+		.collect();
+
+	let Some(id_column) = header.iter().position(|column| column == "id") else {
+		return Vec::new();
+	};
+	let direction_column = header.iter().position(|column| column == "direction");
+
+	valid_ids(lines.filter_map(|line| {
+		let fields: Vec<&str> = line.split(',').map(|field| field.trim().trim_matches('"')).collect();
+
+		if let Some(column) = direction_column {
+			if fields.get(column) != Some(&"up") {
+				return None;
+			}
+		}
+
+		fields.get(id_column).copied()
+	}))
+}
+
+/// Normalizes post IDs, dropping invalid and repeated ones.
+fn valid_ids<'a>(ids: impl Iterator<Item = &'a str>) -> Vec<String> {
+	let mut seen = HashSet::new();
+	ids
+		.map(|id| id.trim_start_matches("t3_").to_lowercase())
+		.filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()))
+		.filter(|id| seen.insert(id.clone()))
+		.collect()
+}
+
+/// Splits the fields of a `multipart/form-data` body into a map of field
+/// names to their (lossily decoded) contents.
+pub fn multipart_fields(content_type: &str, body: &[u8]) -> HashMap<String, String> {
+	let mut fields = HashMap::new();
+
+	let Some(boundary) = content_type.split(';').find_map(|part| part.trim().strip_prefix("boundary=")) else {
+		return fields;
+	};
+	let delimiter = format!("--{}", boundary.trim_matches('"'));
+	let body = String::from_utf8_lossy(body);
+
+	for part in body.split(delimiter.as_str()) {
+		let Some((headers, content)) = part.split_once("\r\n\r\n") else {
+			continue;
+		};
+
+		let name = headers
+			.lines()
+			.filter(|line| line.to_lowercase().starts_with("content-disposition"))
+			.flat_map(|line| line.split(';'))
+			.find_map(|attribute| attribute.trim().strip_prefix("name=").map(|name| name.trim_matches('"').to_string()));
+
+		if let Some(name) = name {
+			fields.insert(name, content.strip_suffix("\r\n").unwrap_or(content).to_string());
+		}
+	}
+
+	fields
+}
+
+/// Imports or clears a list from the settings page.
+pub async fn import(req: Request<Body>) -> Result<Response<Body>, String> {
+	let content_type = req
+		.headers()
+		.get(header::CONTENT_TYPE)
+		.and_then(|value| value.to_str().ok())
+		.unwrap_or_default()
+		.to_string();
+
+	// Reject oversized uploads up front, and stop reading as soon as a body
+	// without a trustworthy length grows past the limit
+	let too_large = || Err("Export file is too large".to_string());
+	let declared = req.headers().get(header::CONTENT_LENGTH).and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+	if declared.is_some_and(|length| length > MAX_UPLOAD) {
+		return too_large();
+	}
+
+	let mut chunks = req.into_body();
+	let mut body = Vec::new();
+	while let Some(chunk) = chunks.next().await {
+		body.extend_from_slice(&chunk.map_err(|e| e.to_string())?);
+		if body.len() > MAX_UPLOAD {
+			return too_large();
+		}
+	}
+
+	let fields = multipart_fields(&content_type, &body);
+	let Some(&(list, _, route)) = LISTS.iter().find(|(name, _, _)| fields.get("list").map(String::as_str) == Some(name)) else {
+		return Err("Unknown list".to_string());
+	};
+
+	let ids = if fields.get("action").map(String::as_str) == Some("clear") {
+		Vec::new()
+	} else {
+		parse_export(fields.get("file").map(String::as_str).unwrap_or_default())
+	};
+
+	let (packed, dropped) = pack_ids(&ids);
+	if packed.is_empty() {
+		let mut response = redirect("/settings".to_string());
+		response.remove_cookie(list.to_string());
+		return Ok(response);
+	}
+
+	// Let the list page say how much of the export was left out
+	let mut response = redirect(if dropped > 0 { format!("{}?dropped={}", route, dropped) } else { route.to_string() });
+	response.insert_cookie(
+		Cookie::build(list, packed)
+			.path("/")
+			.http_only(true)
+			.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
+			.finish(),
+	);
+
+	Ok(response)
+}
+
+/// Downloads an imported list as JSON, to back it up or import it on another
+/// device or instance. Posts Reddit no longer has are kept, marked as deleted.
+pub async fn export(req: Request<Body>) -> Result<Response<Body>, String> {
+	let Some(list) = list_name(req.uri().path().trim_end_matches('/').trim_end_matches("/export")) else {
+		return error(req, "Unknown list").await;
+	};
+
+	let ids = stored_ids(&req, list);
+	let mut found: HashMap<String, Post> = HashMap::new();
+	for batch in ids.chunks(BY_ID_BATCH) {
+		let names: Vec<String> = batch.iter().map(|id| format!("t3_{}", id)).collect();
+		match Post::fetch(&format!("/by_id/{}.json?raw_json=1", names.join(",")), false).await {
+			Ok((posts, _)) => found.extend(posts.into_iter().map(|post| (post.id.clone(), post))),
+			Err(e) if e.msg == "No posts found" => {}
+			Err(msg) => return error(req, msg).await,
+		}
+	}
+
+	let posts: Vec<Value> = ids
+		.iter()
+		.map(|id| match found.get(id) {
+			Some(post) => json!({
+				"id": id,
+				"title": post.title,
+				"subreddit": post.community,
+				"permalink": post.permalink,
+				"deleted": false,
+			}),
+			None => json!({ "id": id, "deleted": true }),
+		})
+		.collect();
+
+	Ok(
+		Response::builder()
+			.status(200)
+			.header("content-type", "application/json")
+			.header("content-disposition", format!("attachment; filename=\"libreddit_{}.json\"", list))
+			.header("cache-control", "no-store")
+			.body(serde_json::to_string_pretty(&json!({ "list": list, "posts": posts })).unwrap_or_default().into())
+			.unwrap_or_default(),
+	)
+}
+
+/// Renders a page of an imported list, fetching the posts by ID.
+pub async fn list(req: Request<Body>) -> Result<Response<Body>, String> {
+	let Some(list) = list_name(req.uri().path()) else {
+		return error(req, "Unknown list").await;
+	};
+
+	let ids = stored_ids(&req, list);
+	if ids.is_empty() {
+		return error_with_status(
+			req,
+			404,
+			format!("No {} posts have been imported. Import them from your Reddit data export in settings.", list),
+		)
+		.await;
+	}
+
+	let query = format!("?{}", req.uri().query().unwrap_or_default());
+	let pages = ids.len().div_ceil(PAGE_SIZE);
+	let page = param(&query, "page").and_then(|page| page.parse::<usize>().ok()).unwrap_or(1).clamp(1, pages);
+	let dropped = param(&query, "dropped").and_then(|dropped| dropped.parse::<usize>().ok()).unwrap_or_default();
+
+	let requested = &ids[(page - 1) * PAGE_SIZE..(page * PAGE_SIZE).min(ids.len())];
+	let names: Vec<String> = requested.iter().map(|id| format!("t3_{}", id)).collect();
+	let path = format!("/by_id/{}.json?raw_json=1", names.join(","));
+
+	// Reddit leaves out posts that no longer exist, so an empty page is not an error
+	let mut posts = match Post::fetch(&path, false).await {
+		Ok((posts, _)) => posts,
+		Err(e) if e.msg == "No posts found" => Vec::new(),
+		Err(msg) => return error(req, msg).await,
+	};
+
+	let missing = requested.len().saturating_sub(posts.len());
+	let filters = get_filters(&req);
+	let (_, all_posts_filtered) = filter_posts(&mut posts, &filters);
+	if setting(&req, "show_nsfw") != "on" {
+		posts.retain(|post| !post.flags.nsfw);
+	}
+
+	let url = req.uri().to_string();
+	template(ImportedTemplate {
+		list: list.to_string(),
+		posts,
+		page,
+		pages,
+		total: ids.len(),
+		missing,
+		dropped,
+		all_posts_filtered,
+		prefs: Preferences::new(&req),
+		url,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{multipart_fields, pack_ids, parse_export, MAX_LIST_SIZE};
+
+	#[test]
+	fn test_parse_export() {
+		let saved = "id,permalink\nabc123,https://www.reddit.com/r/rust/comments/abc123/title/\nt3_DEF456,https://www.reddit.com/r/rust/comments/def456/\nabc123,duplicate\n,\n";
+		assert_eq!(parse_export(saved), vec!["abc123", "def456"]);
+
+		let votes = "id,permalink,direction\naaa111,link,up\nbbb222,link,down\nccc333,link,none\n";
+		assert_eq!(parse_export(votes), vec!["aaa111"]);
+
+		assert!(parse_export("not,an,export\n1,2,3").is_empty());
+
+		let exported = r#"{"list": "saved", "posts": [{"id": "abc123", "title": "A"}, {"id": "gone", "deleted": true}, {"id": "DEF456", "deleted": false}, {"id": "bad id"}]}"#;
+		assert_eq!(parse_export(exported), vec!["abc123", "def456"]);
+	}
+
+	#[test]
+	fn test_pack_ids() {
+		assert_eq!(pack_ids(&["abc".to_string(), "def".to_string()]), ("abc+def".to_string(), 0));
+
+		let ids: Vec<String> = (0..1000).map(|n| format!("{:06}", n)).collect();
+		let (packed, dropped) = pack_ids(&ids);
+		assert!(packed.len() <= MAX_LIST_SIZE);
+		assert_eq!(packed.split('+').count() + dropped, ids.len());
+		assert!(packed.starts_with("000000+000001+"));
+	}
+
+	#[test]
+	fn test_multipart_fields() {
+		let body = "--XyZ\r\nContent-Disposition: form-data; name=\"list\"\r\n\r\nsaved\r\n--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"saved_posts.csv\"\r\nContent-Type: text/csv\r\n\r\nid,permalink\nabc,x\r\n--XyZ--\r\n";
+		let fields = multipart_fields("multipart/form-data; boundary=XyZ", body.as_bytes());
+		assert_eq!(fields.get("list").map(String::as_str), Some("saved"));
+		assert_eq!(fields.get("file").map(String::as_str), Some("id,permalink\nabc,x"));
+		assert!(multipart_fields("text/plain", body.as_bytes()).is_empty());
+	}
+}
@@ -1,5 +1,8 @@
 // CRATES
-use crate::utils::{self, catch_random, error, filter_posts, format_num, format_url, get_filters, param, redirect, setting, template, val, Post, Preferences};
+use crate::utils::{
+	self, cache, catch_random, debug_json, debug_response, error, filter_posts, format_num, format_url, get_filters, param, redirect, setting, template, val, CachePolicy, Post,
+	Preferences,
+};
 use crate::{
 	client::json,
 	subreddit::{can_access_quarantine, quarantine},
@@ -60,7 +63,10 @@ pub async fn find(req: Request<Body>) -> Result<Response<Body>, String> {
 	} else {
 		""
 	};
-	let path = format!("{}.json?{}{}&raw_json=1", req.uri().path(), req.uri().query().unwrap_or_default(), nsfw_results);
+	let mut path = format!("{}.json?{}{}&raw_json=1", req.uri().path(), req.uri().query().unwrap_or_default(), nsfw_results);
+	if param(&path, "restrict_sr").is_none() {
+		path.push_str("&sr_detail=1");
+	}
 	let mut query = param(&path, "q").unwrap_or_default();
 	query = REDDIT_URL_MATCH.replace(&query, "").to_string();
 
@@ -81,6 +87,10 @@ pub async fn find(req: Request<Body>) -> Result<Response<Body>, String> {
 
 	let typed = param(&path, "type").unwrap_or_default();
 
+	if debug_json(&req) {
+		return debug_response(&path, quarantined).await;
+	}
+
 	let sort = param(&path, "sort").unwrap_or_else(|| "relevance".to_string());
 	let filters = get_filters(&req);
 
@@ -97,59 +107,65 @@ pub async fn find(req: Request<Body>) -> Result<Response<Body>, String> {
 
 	// If all requested subs are filtered, we don't need to fetch posts.
 	if sub.split('+').all(|s| filters.contains(s)) {
-		template(SearchTemplate {
-			posts: Vec::new(),
-			subreddits,
-			sub,
-			params: SearchParams {
-				q: query.replace('"', "&quot;"),
-				sort,
-				t: param(&path, "t").unwrap_or_default(),
-				before: param(&path, "after").unwrap_or_default(),
-				after: "".to_string(),
-				restrict_sr: param(&path, "restrict_sr").unwrap_or_default(),
-				typed,
-			},
-			prefs: Preferences::new(&req),
-			url,
-			is_filtered: true,
-			all_posts_filtered: false,
-			all_posts_hidden_nsfw: false,
-			no_posts: false,
-		})
+		cache(
+			template(SearchTemplate {
+				posts: Vec::new(),
+				subreddits,
+				sub,
+				params: SearchParams {
+					q: query.replace('"', "&quot;"),
+					sort,
+					t: param(&path, "t").unwrap_or_default(),
+					before: param(&path, "after").unwrap_or_default(),
+					after: "".to_string(),
+					restrict_sr: param(&path, "restrict_sr").unwrap_or_default(),
+					typed,
+				},
+				prefs: Preferences::new(&req),
+				url,
+				is_filtered: true,
+				all_posts_filtered: false,
+				all_posts_hidden_nsfw: false,
+				no_posts: false,
+			}),
+			CachePolicy::Listing,
+		)
 	} else {
 		match Post::fetch(&path, quarantined).await {
 			Ok((mut posts, after)) => {
 				let (_, all_posts_filtered) = filter_posts(&mut posts, &filters);
 				let no_posts = posts.is_empty();
 				let all_posts_hidden_nsfw = !no_posts && (posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on");
-				template(SearchTemplate {
-					posts,
-					subreddits,
-					sub,
-					params: SearchParams {
-						q: query.replace('"', "&quot;"),
-						sort,
-						t: param(&path, "t").unwrap_or_default(),
-						before: param(&path, "after").unwrap_or_default(),
-						after,
-						restrict_sr: param(&path, "restrict_sr").unwrap_or_default(),
-						typed,
-					},
-					prefs: Preferences::new(&req),
-					url,
-					is_filtered: false,
-					all_posts_filtered,
-					all_posts_hidden_nsfw,
-					no_posts,
-				})
+				cache(
+					template(SearchTemplate {
+						posts,
+						subreddits,
+						sub,
+						params: SearchParams {
+							q: query.replace('"', "&quot;"),
+							sort,
+							t: param(&path, "t").unwrap_or_default(),
+							before: param(&path, "after").unwrap_or_default(),
+							after,
+							restrict_sr: param(&path, "restrict_sr").unwrap_or_default(),
+							typed,
+						},
+						prefs: Preferences::new(&req),
+						url,
+						is_filtered: false,
+						all_posts_filtered,
+						all_posts_hidden_nsfw,
+						no_posts,
+					}),
+					CachePolicy::Listing,
+				)
 			}
-			Err(msg) => {
-				if msg == "quarantined" || msg == "gated" {
+			Err(e) => {
+				if e.msg == "quarantined" || e.msg == "gated" {
 					let sub = req.param("sub").unwrap_or_default();
-					quarantine(req, sub, msg)
+					quarantine(req, sub, e.msg)
 				} else {
-					error(req, msg).await
+					error(req, e).await
 				}
 			}
 		}
@@ -1,3 +1,4 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use brotli::enc::{BrotliCompress, BrotliEncoderParams};
 use cached::proc_macro::cached;
 use cookie::Cookie;
@@ -7,15 +8,18 @@ use hyper::{
 	body,
 	body::HttpBody,
 	header,
+	server::conn::AddrStream,
 	service::{make_service_fn, service_fn},
 	HeaderMap,
 };
 use hyper::{Body, Method, Request, Response, Server as HyperServer};
 use libflate::gzip;
 use route_recognizer::{Params, Router};
+use sha2::{Digest, Sha256};
 use std::{
 	cmp::Ordering,
-	io,
+	io::{self, Write},
+	net::IpAddr,
 	pin::Pin,
 	result::Result,
 	str::{from_utf8, Split},
@@ -24,7 +28,7 @@ use std::{
 };
 use time::Duration;
 
-use crate::{config, dbg_msg, instance_info::INSTANCE_INFO};
+use crate::{client, config, dbg_msg, instance_info::INSTANCE_INFO, utils::setting};
 
 type BoxResponse = Pin<Box<dyn Future<Output = Result<Response<Body>, String>> + Send>>;
 
@@ -66,12 +70,12 @@ impl CompressionType {
 	}
 }
 
-impl ToString for CompressionType {
-	fn to_string(&self) -> String {
+impl std::fmt::Display for CompressionType {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		match self {
-			CompressionType::Gzip => "gzip".to_string(),
-			CompressionType::Brotli => "br".to_string(),
-			_ => String::new(),
+			CompressionType::Gzip => write!(f, "gzip"),
+			CompressionType::Brotli => write!(f, "br"),
+			_ => Ok(()),
 		}
 	}
 }
@@ -83,6 +87,23 @@ pub struct Route<'a> {
 
 pub struct Server {
 	pub default_headers: HeaderMap,
+	/// Whether plain HTTP requests are redirected to HTTPS.
+	pub redirect_https: bool,
+	/// Path prefixes that are served over plain HTTP even when
+	/// `redirect_https` is set (e.g. health checks).
+	pub https_exempt_paths: Vec<String>,
+	/// How long in-flight requests get to complete once shutdown begins.
+	pub shutdown_timeout: tokio::time::Duration,
+	/// Path prefix Libreddit is hosted under (e.g. `/reddit`), stripped from
+	/// requests and added to redirects. Empty when hosted at the root.
+	pub base_path: String,
+	/// Reverse proxies whose `X-Forwarded-For` and `X-Forwarded-Proto`
+	/// headers are believed.
+	pub trusted_proxies: Vec<IpNet>,
+	/// Credentials every request must carry, if the instance is private.
+	pub auth: Option<BasicAuth>,
+	/// Path prefixes served without credentials (e.g. health checks).
+	pub auth_exempt_paths: Vec<String>,
 	router: Router<fn(Request<Body>) -> BoxResponse>,
 }
 
@@ -105,12 +126,12 @@ pub trait RequestExt {
 	fn params(&self) -> Params;
 	fn param(&self, name: &str) -> Option<String>;
 	fn set_params(&mut self, params: Params) -> Option<Params>;
-	fn cookies(&self) -> Vec<Cookie>;
-	fn cookie(&self, name: &str) -> Option<Cookie>;
+	fn cookies(&self) -> Vec<Cookie<'_>>;
+	fn cookie(&self, name: &str) -> Option<Cookie<'_>>;
+	fn client_ip(&self) -> Option<IpAddr>;
 }
 
 pub trait ResponseExt {
-	fn cookies(&self) -> Vec<Cookie>;
 	fn insert_cookie(&mut self, cookie: Cookie);
 	fn remove_cookie(&mut self, name: String);
 }
@@ -132,7 +153,7 @@ impl RequestExt for Request<Body> {
 		self.extensions_mut().insert(params)
 	}
 
-	fn cookies(&self) -> Vec<Cookie> {
+	fn cookies(&self) -> Vec<Cookie<'_>> {
 		self.headers().get("Cookie").map_or(Vec::new(), |header| {
 			header
 				.to_str()
@@ -143,23 +164,18 @@ impl RequestExt for Request<Body> {
 		})
 	}
 
-	fn cookie(&self, name: &str) -> Option<Cookie> {
+	fn cookie(&self, name: &str) -> Option<Cookie<'_>> {
 		self.cookies().into_iter().find(|c| c.name() == name)
 	}
-}
 
-impl ResponseExt for Response<Body> {
-	fn cookies(&self) -> Vec<Cookie> {
-		self.headers().get("Cookie").map_or(Vec::new(), |header| {
-			header
-				.to_str()
-				.unwrap_or_default()
-				.split("; ")
-				.map(|cookie| Cookie::parse(cookie).unwrap_or_else(|_| Cookie::named("")))
-				.collect()
-		})
+	/// Address of the client, as reported by a trusted reverse proxy if the
+	/// request came through one
+	fn client_ip(&self) -> Option<IpAddr> {
+		self.extensions().get::<IpAddr>().copied()
 	}
+}
 
+impl ResponseExt for Response<Body> {
 	fn insert_cookie(&mut self, cookie: Cookie) {
 		if let Ok(val) = header::HeaderValue::from_str(&cookie.to_string()) {
 			self.headers_mut().append("Set-Cookie", val);
@@ -197,11 +213,18 @@ impl Server {
 	pub fn new() -> Self {
 		Server {
 			default_headers: HeaderMap::new(),
+			redirect_https: false,
+			https_exempt_paths: Vec::new(),
+			shutdown_timeout: tokio::time::Duration::from_secs(30),
+			base_path: String::new(),
+			trusted_proxies: Vec::new(),
+			auth: None,
+			auth_exempt_paths: Vec::new(),
 			router: Router::new(),
 		}
 	}
 
-	pub fn at(&mut self, path: &str) -> Route {
+	pub fn at(&mut self, path: &str) -> Route<'_> {
 		Route {
 			path: path.to_owned(),
 			router: &mut self.router,
@@ -209,19 +232,46 @@ impl Server {
 	}
 
 	pub fn listen(self, addr: String) -> Boxed<Result<(), hyper::Error>> {
-		let make_svc = make_service_fn(move |_conn| {
+		let make_svc = make_service_fn(move |conn: &AddrStream| {
+			let peer = conn.remote_addr().ip().to_canonical();
+
 			// For correct borrowing, these values need to be borrowed
 			let router = self.router.clone();
 			let default_headers = self.default_headers.clone();
+			let redirect_https = self.redirect_https;
+			let https_exempt_paths = self.https_exempt_paths.clone();
+			let base_path = self.base_path.clone();
+			let trusted_proxies = self.trusted_proxies.clone();
+			let auth = self.auth.clone();
+			let auth_exempt_paths = self.auth_exempt_paths.clone();
 
 			// This is the `Service` that will handle the connection.
 			// `service_fn` is a helper to convert a function that
 			// returns a Response into a `Service`.
 			// let shared_router = router.clone();
 			async move {
-				Ok::<_, String>(service_fn(move |req: Request<Body>| {
+				Ok::<_, String>(service_fn(move |mut req: Request<Body>| {
 					let req_headers = req.headers().clone();
-					let def_headers = default_headers.clone();
+					let mut def_headers = default_headers.clone();
+					let base_path = base_path.clone();
+
+					// Forwarded headers only count when a trusted proxy set them
+					let forwarded_proto_trusted = trusted_proxies.iter().any(|net| net.contains(&peer));
+
+					// Browsers ignore HSTS over plain HTTP, and sending it anyway
+					// would lock HTTP-only instances out once they're behind TLS
+					if !(forwarded_proto_trusted && is_secure(&req_headers)) {
+						def_headers.remove(header::STRICT_TRANSPORT_SECURITY);
+					}
+					req.extensions_mut().insert(client_ip(peer, &req_headers, &trusted_proxies));
+
+					// Route requests under the base path as if Libreddit were hosted at the root
+					if let Some(rest) = strip_base_path(req.uri().path(), &base_path) {
+						let stripped = req.uri().query().map_or_else(|| rest.to_string(), |query| format!("{}?{}", rest, query));
+						if let Ok(uri) = stripped.parse() {
+							*req.uri_mut() = uri;
+						}
+					}
 
 					// Remove double slashes and decode encoded slashes
 					let mut path = req.uri().path().replace("//", "/").replace("%2F", "/");
@@ -231,6 +281,36 @@ impl Server {
 						path.pop();
 					}
 
+					// Send plain HTTP requests over to HTTPS, unless the path is exempt
+					if redirect_https && !(forwarded_proto_trusted && is_secure(&req_headers)) && !is_exempt(&path, &https_exempt_paths) {
+						if let Some(host) = req_headers.get(header::HOST).and_then(|h| h.to_str().ok()) {
+							let location = format!("https://{}{}{}", host, base_path, req.uri().path_and_query().map_or("/", |pq| pq.as_str()));
+							let status = https_redirect_status(req.method());
+							return async move {
+								let mut res = new_boilerplate(def_headers, req_headers, status, Body::empty()).await?;
+								if let Ok(val) = header::HeaderValue::from_str(&location) {
+									res.headers_mut().insert(header::LOCATION, val);
+								}
+								Ok(res)
+							}
+							.boxed();
+						}
+					}
+
+					// Ask for credentials on private instances
+					if let Some(auth) = &auth {
+						if !is_exempt(&path, &auth_exempt_paths) && !auth.allows(&req_headers) {
+							return async move {
+								let mut res = new_boilerplate(def_headers, req_headers, 401, Body::from("Unauthorized")).await?;
+								res
+									.headers_mut()
+									.insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Basic realm=\"Libreddit\", charset=\"UTF-8\""));
+								Ok(res)
+							}
+							.boxed();
+						}
+					}
+
 					// Match the visited path with an added route
 					match router.recognize(&format!("/{}{}", req.method().as_str(), path)) {
 						// If a route was configured for this path
@@ -243,12 +323,20 @@ impl Server {
 							let mut parammed = req;
 							parammed.set_params(found.params().clone());
 
-							// Run the route's function
-							let func = (found.handler().to_owned().to_owned())(parammed);
+							// Run the route's function, in the language chosen in the
+							// visitor's settings or else their browser's
+							let language = Some(setting(&parammed, "lang"))
+								.filter(|lang| !lang.is_empty())
+								.or_else(|| req_headers.get(header::ACCEPT_LANGUAGE).and_then(|val| val.to_str().ok()).map(String::from))
+								.and_then(|languages| primary_language(&languages))
+								.unwrap_or_default();
+							let client_ip = parammed.client_ip();
+							let func = client::CLIENT_IP.scope(client_ip, client::LANGUAGE.scope(language, (found.handler().to_owned().to_owned())(parammed)));
 							async move {
 								match func.await {
 									Ok(mut res) => {
 										res.headers_mut().extend(def_headers);
+										rebase_location(&mut res, &base_path);
 										let _ = compress_response(&req_headers, &mut res).await;
 
 										Ok(res)
@@ -268,16 +356,297 @@ impl Server {
 		// Build SocketAddr from provided address
 		let address = &addr.parse().unwrap_or_else(|_| panic!("Cannot parse {} as address (example format: 0.0.0.0:8080)", addr));
 
-		// Bind server to address specified above. Gracefully shut down if CTRL+C is pressed
-		let server = HyperServer::bind(address).serve(make_svc).with_graceful_shutdown(async {
-			// Wait for the CTRL+C signal
-			tokio::signal::ctrl_c().await.expect("Failed to install CTRL+C signal handler");
+		let shutdown_timeout = self.shutdown_timeout;
+		let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+
+		// Bind server to address specified above. Gracefully shut down on CTRL+C
+		// or SIGTERM: stop accepting connections and let in-flight requests
+		// finish.
+		let server = HyperServer::bind(address).serve(make_svc).with_graceful_shutdown(async move {
+			shutdown_signal().await;
+			println!("Shutting down, waiting up to {}s for in-flight requests to complete...", shutdown_timeout.as_secs());
+			let _ = shutdown_tx.send(());
 		});
 
-		server.boxed()
+		async move {
+			tokio::select! {
+				res = server => res,
+				// Give up on requests that outlive the shutdown timeout
+				() = async {
+					if shutdown_rx.await.is_ok() {
+						tokio::time::sleep(shutdown_timeout).await;
+					} else {
+						std::future::pending::<()>().await;
+					}
+				} => {
+					eprintln!("Shutdown timeout reached, dropping remaining connections");
+					Ok(())
+				}
+			}
+		}
+		.boxed()
+	}
+}
+
+/// Resolves once the process is asked to stop, either by CTRL+C or, on Unix,
+/// by SIGTERM (as sent by e.g. Docker or systemd during a deploy).
+async fn shutdown_signal() {
+	let ctrl_c = async {
+		tokio::signal::ctrl_c().await.expect("Failed to install CTRL+C signal handler");
+	};
+
+	#[cfg(unix)]
+	let terminate = async {
+		tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+			.expect("Failed to install SIGTERM signal handler")
+			.recv()
+			.await;
+	};
+
+	#[cfg(not(unix))]
+	let terminate = std::future::pending::<()>();
+
+	tokio::select! {
+		() = ctrl_c => {},
+		() = terminate => {},
 	}
 }
 
+/// Returns true if the request reached us over HTTPS. Libreddit itself only
+/// speaks plain HTTP, so this relies on the `X-Forwarded-Proto` header set by
+/// a TLS-terminating reverse proxy.
+fn is_secure(req_headers: &HeaderMap<header::HeaderValue>) -> bool {
+	req_headers
+		.get("X-Forwarded-Proto")
+		.and_then(|val| val.to_str().ok())
+		.is_some_and(|proto| proto.eq_ignore_ascii_case("https"))
+}
+
+/// The primary language subtag (`de` for `de-DE`) of the first language in an
+/// `Accept-Language` header or language setting. Only that is passed on to
+/// Reddit, as the full list says more about a visitor than Reddit needs to
+/// know, and responses are cached by it.
+fn primary_language(languages: &str) -> Option<String> {
+	let language = languages.split(',').next()?.split(';').next()?.trim().split('-').next()?;
+	let valid = (2..=8).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic());
+	valid.then(|| language.to_ascii_lowercase())
+}
+
+/// Status code for sending a request over to HTTPS. Browsers turn a 301 into
+/// a GET, dropping the body of form submissions like `POST /settings`, so
+/// anything but a GET or HEAD is redirected with a 308 that keeps it.
+fn https_redirect_status(method: &Method) -> u16 {
+	if method == Method::GET || method == Method::HEAD {
+		301
+	} else {
+		308
+	}
+}
+
+/// Returns true if `path` falls under one of the `exempt` path prefixes. A
+/// prefix only matches on a path segment boundary, so `/healthz` exempts
+/// `/healthz` and `/healthz/ready` but not `/healthzfoo`.
+fn is_exempt(path: &str, exempt: &[String]) -> bool {
+	exempt
+		.iter()
+		.map(|prefix| prefix.trim_end_matches('/'))
+		.filter(|prefix| !prefix.is_empty())
+		.any(|prefix| path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/')))
+}
+
+/// HTTP basic auth credentials: a user name and the hex-encoded SHA-256
+/// hash of its password, given as `user:hash`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicAuth {
+	user: String,
+	hash: String,
+}
+
+impl std::str::FromStr for BasicAuth {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (user, hash) = s.trim().split_once(':').ok_or("expected user:hash")?;
+		if user.is_empty() {
+			return Err("missing user name".to_string());
+		}
+		if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+			return Err("hash must be the hex-encoded SHA-256 of the password".to_string());
+		}
+
+		Ok(Self {
+			user: user.to_string(),
+			hash: hash.to_lowercase(),
+		})
+	}
+}
+
+impl BasicAuth {
+	/// Returns true if the request's `Authorization` header carries these credentials
+	fn allows(&self, req_headers: &HeaderMap<header::HeaderValue>) -> bool {
+		let Some(decoded) = req_headers
+			.get(header::AUTHORIZATION)
+			.and_then(|val| val.to_str().ok())
+			.and_then(|val| val.strip_prefix("Basic "))
+			.and_then(|encoded| BASE64.decode(encoded.trim()).ok())
+			.and_then(|decoded| String::from_utf8(decoded).ok())
+		else {
+			return false;
+		};
+		let Some((user, password)) = decoded.split_once(':') else {
+			return false;
+		};
+
+		let hash: String = Sha256::digest(password.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect();
+
+		// Compare the whole hash so the time taken doesn't give away how much matched
+		let matches = hash.bytes().zip(self.hash.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0;
+		matches && user == self.user
+	}
+}
+
+/// A block of IP addresses in CIDR notation, e.g. `10.0.0.0/8`. A bare
+/// address stands for just itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IpNet {
+	addr: IpAddr,
+	prefix: u8,
+}
+
+impl std::str::FromStr for IpNet {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (addr, prefix) = s.trim().split_once('/').map_or((s.trim(), None), |(addr, prefix)| (addr, Some(prefix)));
+		let addr: IpAddr = addr.parse().map_err(|_| format!("invalid IP address in {}", s))?;
+		let max = if addr.is_ipv4() { 32 } else { 128 };
+		let prefix = match prefix {
+			Some(prefix) => prefix
+				.parse::<u8>()
+				.ok()
+				.filter(|prefix| *prefix <= max)
+				.ok_or_else(|| format!("invalid prefix length in {}", s))?,
+			None => max,
+		};
+
+		Ok(Self { addr, prefix })
+	}
+}
+
+impl IpNet {
+	/// Returns true if `ip` falls within this block
+	pub fn contains(&self, ip: &IpAddr) -> bool {
+		let (net, ip, bits) = match (self.addr, ip.to_canonical()) {
+			(IpAddr::V4(net), IpAddr::V4(ip)) => (u128::from(u32::from(net)), u128::from(u32::from(ip)), 32),
+			(IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip), 128),
+			_ => return false,
+		};
+
+		let shift = bits - u32::from(self.prefix);
+		shift == bits || (net >> shift) == (ip >> shift)
+	}
+}
+
+/// Determines the client's address. `X-Forwarded-For` is only read when the
+/// `peer` is a trusted proxy, from the right, skipping the trusted proxies
+/// in front of us; everything left of the first untrusted hop could have
+/// been made up by the client.
+fn client_ip(peer: IpAddr, req_headers: &HeaderMap<header::HeaderValue>, trusted: &[IpNet]) -> IpAddr {
+	let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+	if !is_trusted(&peer) {
+		return peer;
+	}
+
+	let hops: Vec<&str> = req_headers
+		.get_all("X-Forwarded-For")
+		.iter()
+		.filter_map(|val| val.to_str().ok())
+		.flat_map(|val| val.split(','))
+		.collect();
+
+	let mut client = peer;
+	for hop in hops.iter().rev() {
+		match hop.trim().parse::<IpAddr>() {
+			Ok(ip) => {
+				client = ip.to_canonical();
+				if !is_trusted(&client) {
+					break;
+				}
+			}
+			Err(_) => break,
+		}
+	}
+
+	client
+}
+
+/// Returns `path` relative to `base`, or `None` if it lies outside it.
+/// Requests outside the base path are routed unchanged, so reverse proxies
+/// that strip the prefix themselves keep working.
+fn strip_base_path<'a>(path: &'a str, base: &str) -> Option<&'a str> {
+	if base.is_empty() {
+		return None;
+	}
+
+	match path.strip_prefix(base)? {
+		"" => Some("/"),
+		rest if rest.starts_with('/') => Some(rest),
+		_ => None,
+	}
+}
+
+/// Adds `base` to a root-relative `Location` header, so redirects stay under
+/// the base path.
+fn rebase_location(res: &mut Response<Body>, base: &str) {
+	let Some(location) = res.headers().get(header::LOCATION).and_then(|val| val.to_str().ok()) else {
+		return;
+	};
+
+	if base.is_empty() || !location.starts_with('/') || location.starts_with("//") {
+		return;
+	}
+
+	if let Ok(val) = header::HeaderValue::from_str(&format!("{}{}", base, location)) {
+		res.headers_mut().insert(header::LOCATION, val);
+	}
+}
+
+/// Appends the directives in `extra` to the Content-Security-Policy `csp`.
+/// Sources for a directive `csp` already has are added to it, replacing
+/// `'none'`; other directives are added as-is.
+pub fn extend_csp(csp: &str, extra: &str) -> String {
+	let mut directives: Vec<(String, Vec<String>)> = Vec::new();
+
+	for directive in csp.split(';').chain(extra.split(';')) {
+		let mut words = directive.split_whitespace().map(ToString::to_string);
+		let Some(name) = words.next().map(|name| name.to_lowercase()) else {
+			continue;
+		};
+		let sources: Vec<String> = words.collect();
+
+		match directives.iter_mut().find(|(existing, _)| *existing == name) {
+			Some((_, existing)) => {
+				existing.retain(|source| source != "'none'");
+				for source in sources {
+					if source != "'none'" && !existing.contains(&source) {
+						existing.push(source);
+					}
+				}
+				if existing.is_empty() {
+					existing.push("'none'".to_string());
+				}
+			}
+			None => directives.push((name, sources)),
+		}
+	}
+
+	directives
+		.iter()
+		.map(|(name, sources)| format!("{} {};", name, sources.join(" ")).replace(" ;", ";"))
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
 /// Create a boilerplate Response for error conditions. This response will be
 /// compressed if requested by client.
 async fn new_boilerplate(
@@ -351,6 +720,7 @@ fn determine_compressor(accept_encoding: String) -> Option<CompressionType> {
 		}
 	}
 
+	#[allow(clippy::non_canonical_partial_ord_impl)]
 	impl PartialOrd for CompressorCandidate {
 		fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
 			// Guard against NAN, both on our end and on the other.
@@ -505,8 +875,10 @@ async fn compress_response(req_headers: &HeaderMap<header::HeaderValue>, res: &m
 
 	// Don't bother if the size of the size of the response body will fit
 	// within an IP frame (less the bytes that make up the TCP/IP and HTTP
-	// headers).
-	if res.body().size_hint().lower() < 1452 {
+	// headers). Streamed bodies have no known size, and are compressed as
+	// they're sent.
+	let streamed = res.body().size_hint().upper().is_none();
+	if !streamed && res.body().size_hint().lower() < 1452 {
 		return Ok(());
 	};
 
@@ -533,6 +905,15 @@ async fn compress_response(req_headers: &HeaderMap<header::HeaderValue>, res: &m
 		None => return Ok(()),
 	};
 
+	if streamed {
+		if let Some(encoder) = StreamEncoder::new(compressor) {
+			let body = std::mem::take(res.body_mut());
+			*(res.body_mut()) = compress_stream(encoder, body);
+			res.headers_mut().insert(header::CONTENT_ENCODING, compressor.to_string().parse().unwrap());
+		}
+		return Ok(());
+	}
+
 	// Get the body from the response.
 	let body_bytes: Vec<u8> = match body::to_bytes(res.body_mut()).await {
 		Ok(b) => b.to_vec(),
@@ -558,6 +939,76 @@ async fn compress_response(req_headers: &HeaderMap<header::HeaderValue>, res: &m
 	Ok(())
 }
 
+/// Compresses a streamed body chunk by chunk with `encoder`. The encoder is
+/// flushed after every chunk, so the client can show each part as it arrives.
+fn compress_stream(mut encoder: StreamEncoder, mut body: Body) -> Body {
+	let (mut sender, compressed) = Body::channel();
+
+	tokio::spawn(async move {
+		while let Some(chunk) = body.data().await {
+			let Some(bytes) = chunk.ok().and_then(|chunk| encoder.push(&chunk).ok()) else {
+				sender.abort();
+				return;
+			};
+			// The client went away
+			if sender.send_data(bytes.into()).await.is_err() {
+				return;
+			}
+		}
+
+		match encoder.finish() {
+			Ok(bytes) => {
+				let _ = sender.send_data(bytes.into()).await;
+			}
+			Err(_) => sender.abort(),
+		}
+	});
+
+	compressed
+}
+
+/// An encoder for bodies that are compressed while they're being sent.
+enum StreamEncoder {
+	Gzip(Box<gzip::Encoder<Vec<u8>>>),
+	Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+}
+
+impl StreamEncoder {
+	fn new(compressor: CompressionType) -> Option<Self> {
+		match compressor {
+			CompressionType::Gzip => gzip::Encoder::new(Vec::new()).ok().map(|gz| Self::Gzip(Box::new(gz))),
+			// Brotli's best quality is too slow to keep up with a stream
+			CompressionType::Brotli => Some(Self::Brotli(Box::new(brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22)))),
+			CompressionType::Passthrough => None,
+		}
+	}
+
+	/// Compresses `chunk`, returning all the output that's ready to be sent.
+	fn push(&mut self, chunk: &[u8]) -> io::Result<Vec<u8>> {
+		let out = match self {
+			Self::Gzip(gz) => {
+				gz.write_all(chunk)?;
+				gz.flush()?;
+				gz.as_inner_mut()
+			}
+			Self::Brotli(br) => {
+				br.write_all(chunk)?;
+				br.flush()?;
+				br.get_mut()
+			}
+		};
+		Ok(std::mem::take(out))
+	}
+
+	/// Ends the stream, returning the output that's left.
+	fn finish(self) -> io::Result<Vec<u8>> {
+		match self {
+			Self::Gzip(gz) => gz.finish().into_result(),
+			Self::Brotli(br) => Ok(br.into_inner()),
+		}
+	}
+}
+
 /// Compresses a `Vec<u8>` given a [`CompressionType`].
 ///
 /// This is a helper function for [`compress_response`] and should not be
@@ -632,6 +1083,115 @@ mod tests {
 	use lipsum::lipsum;
 	use std::{boxed::Box, io};
 
+	#[test]
+	fn test_is_exempt() {
+		let exempt = vec!["/healthz".to_string(), "/metrics/".to_string(), String::new()];
+
+		assert!(is_exempt("/healthz", &exempt));
+		assert!(is_exempt("/healthz/ready", &exempt));
+		assert!(is_exempt("/metrics", &exempt));
+		assert!(!is_exempt("/healthzfoo", &exempt));
+		assert!(!is_exempt("/", &exempt));
+		assert!(!is_exempt("/r/rust", &[]));
+	}
+
+	#[test]
+	fn test_primary_language() {
+		assert_eq!(primary_language("de-DE,de;q=0.9,en;q=0.8"), Some("de".to_string()));
+		assert_eq!(primary_language("fr;q=0.9"), Some("fr".to_string()));
+		assert_eq!(primary_language("PT-br"), Some("pt".to_string()));
+		assert_eq!(primary_language("*"), None);
+		assert_eq!(primary_language("en\r\nX: y"), None);
+		assert_eq!(primary_language(""), None);
+	}
+
+	#[test]
+	fn test_https_redirect_status() {
+		assert_eq!(https_redirect_status(&Method::GET), 301);
+		assert_eq!(https_redirect_status(&Method::HEAD), 301);
+		assert_eq!(https_redirect_status(&Method::POST), 308);
+	}
+
+	#[test]
+	fn test_extend_csp() {
+		let csp = "default-src 'none'; script-src 'self' blob:; frame-ancestors 'none'; upgrade-insecure-requests;";
+
+		assert_eq!(
+			extend_csp(csp, ""),
+			"default-src 'none'; script-src 'self' blob:; frame-ancestors 'none'; upgrade-insecure-requests;"
+		);
+		assert_eq!(
+			extend_csp(csp, "script-src https://cdn.example.com blob:; frame-ancestors https://example.com; Img-Src *"),
+			"default-src 'none'; script-src 'self' blob: https://cdn.example.com; frame-ancestors https://example.com; upgrade-insecure-requests; img-src *;"
+		);
+	}
+
+	#[test]
+	fn test_is_secure() {
+		let mut req_headers = HeaderMap::new();
+		assert!(!is_secure(&req_headers));
+
+		req_headers.insert("X-Forwarded-Proto", header::HeaderValue::from_static("http"));
+		assert!(!is_secure(&req_headers));
+
+		req_headers.insert("X-Forwarded-Proto", header::HeaderValue::from_static("HTTPS"));
+		assert!(is_secure(&req_headers));
+	}
+
+	#[test]
+	fn test_basic_auth() {
+		// SHA-256 of "hunter2"
+		let auth: BasicAuth = "alice:f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7".parse().unwrap();
+		let mut req_headers = HeaderMap::new();
+		assert!(!auth.allows(&req_headers));
+
+		req_headers.insert(
+			header::AUTHORIZATION,
+			header::HeaderValue::from_str(&format!("Basic {}", BASE64.encode("alice:hunter2"))).unwrap(),
+		);
+		assert!(auth.allows(&req_headers));
+
+		req_headers.insert(
+			header::AUTHORIZATION,
+			header::HeaderValue::from_str(&format!("Basic {}", BASE64.encode("alice:hunter3"))).unwrap(),
+		);
+		assert!(!auth.allows(&req_headers));
+
+		req_headers.insert(
+			header::AUTHORIZATION,
+			header::HeaderValue::from_str(&format!("Basic {}", BASE64.encode("bob:hunter2"))).unwrap(),
+		);
+		assert!(!auth.allows(&req_headers));
+
+		assert!("alice:hunter2".parse::<BasicAuth>().is_err());
+	}
+
+	#[test]
+	fn test_client_ip() {
+		let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap(), "::1".parse().unwrap()];
+		let proxy: IpAddr = "10.1.2.3".parse().unwrap();
+		let mut req_headers = HeaderMap::new();
+		req_headers.insert("X-Forwarded-For", header::HeaderValue::from_static("1.1.1.1, 203.0.113.7, 10.0.0.5"));
+
+		assert_eq!(client_ip(proxy, &req_headers, &trusted), "203.0.113.7".parse::<IpAddr>().unwrap());
+		assert_eq!(client_ip("192.0.2.1".parse().unwrap(), &req_headers, &trusted), "192.0.2.1".parse::<IpAddr>().unwrap());
+		assert_eq!(client_ip(proxy, &HeaderMap::new(), &trusted), proxy);
+		assert_eq!(client_ip(proxy, &req_headers, &[]), proxy);
+
+		assert!("::1".parse::<IpNet>().unwrap().contains(&"::1".parse().unwrap()));
+		assert!("0.0.0.0/0".parse::<IpNet>().unwrap().contains(&"::ffff:8.8.8.8".parse().unwrap()));
+		assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+	}
+
+	#[test]
+	fn test_strip_base_path() {
+		assert_eq!(strip_base_path("/reddit/r/rust", "/reddit"), Some("/r/rust"));
+		assert_eq!(strip_base_path("/reddit", "/reddit"), Some("/"));
+		assert_eq!(strip_base_path("/redditor", "/reddit"), None);
+		assert_eq!(strip_base_path("/r/rust", "/reddit"), None);
+		assert_eq!(strip_base_path("/r/rust", ""), None);
+	}
+
 	#[test]
 	fn test_determine_compressor() {
 		// Single compressor given.
@@ -734,7 +1294,7 @@ mod tests {
 
 				CompressionType::Brotli => Box::new(BrotliDecompressor::new(body_cursor, expected_lorem_ipsum.len())),
 
-				_ => panic!("no decompressor for {}", expected_encoding.to_string()),
+				_ => panic!("no decompressor for {}", expected_encoding),
 			};
 
 			let mut decompressed = Vec::<u8>::new();
@@ -745,4 +1305,36 @@ mod tests {
 			assert!(decompressed.eq(&expected_lorem_ipsum));
 		}
 	}
+
+	#[tokio::test]
+	async fn test_compress_streamed_response() {
+		let parts: Vec<String> = (0..20).map(|n| lipsum(200 + n)).collect();
+		let expected = parts.concat().into_bytes();
+
+		for compressor in [CompressionType::Gzip, CompressionType::Brotli] {
+			let (mut sender, body) = Body::channel();
+			let sent = parts.clone();
+			tokio::spawn(async move {
+				for part in sent {
+					sender.send_data(part.into()).await.unwrap();
+				}
+			});
+
+			let mut req_headers = HeaderMap::new();
+			req_headers.insert(header::ACCEPT_ENCODING, header::HeaderValue::from_str(&compressor.to_string()).unwrap());
+			let mut res = Response::builder().status(200).header(header::CONTENT_TYPE, "text/html").body(body).unwrap();
+			compress_response(&req_headers, &mut res).await.unwrap();
+			assert_eq!(res.headers().get(header::CONTENT_ENCODING).unwrap().to_str().unwrap(), compressor.to_string());
+
+			let compressed = body::to_bytes(res.into_body()).await.unwrap().to_vec();
+			assert!(compressed.len() < expected.len());
+			let mut decoder: Box<dyn io::Read> = match compressor {
+				CompressionType::Gzip => Box::new(gzip::Decoder::new(io::Cursor::new(compressed)).unwrap()),
+				_ => Box::new(BrotliDecompressor::new(io::Cursor::new(compressed), 4096)),
+			};
+			let mut decompressed = Vec::new();
+			io::copy(&mut decoder, &mut decompressed).unwrap();
+			assert_eq!(decompressed, expected);
+		}
+	}
 }
@@ -1,8 +1,11 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 // CRATES
+use crate::saved;
 use crate::server::ResponseExt;
-use crate::utils::{redirect, template, Preferences};
+use crate::subreddit::MAX_COOKIE_VALUE_LENGTH;
+use crate::utils::{cache, error_with_status, format_feeds, format_sub_sorts, parse_feeds, parse_sub_sorts, redirect, seen_posts, template, CachePolicy, Preferences};
 use askama::Template;
 use cookie::Cookie;
 use futures_lite::StreamExt;
@@ -14,12 +17,16 @@ use time::{Duration, OffsetDateTime};
 #[template(path = "settings.html")]
 struct SettingsTemplate {
 	prefs: Preferences,
+	/// Imported lists as (name, export file, route, number of posts)
+	imported: Vec<(&'static str, &'static str, &'static str, usize)>,
+	/// Number of posts marked as seen
+	seen: usize,
 	url: String,
 }
 
 // CONSTANTS
 
-const PREFS: [&str; 13] = [
+const PREFS: [&str; 40] = [
 	"theme",
 	"front_page",
 	"layout",
@@ -33,6 +40,33 @@ const PREFS: [&str; 13] = [
 	"autoplay_videos",
 	"hide_awards",
 	"disable_visit_reddit_confirmation",
+	"show_controversial",
+	"data_saver",
+	"full_res_images",
+	"comment_limit",
+	"show_post_flair",
+	"show_user_flair",
+	"animate_thumbnails",
+	"preview_quality",
+	"show_vote_breakdown",
+	"show_upvote_ratio",
+	"show_vote_buttons",
+	"show_subscriber_counts",
+	"render_math",
+	"inline_threads",
+	"subreddit_names",
+	"link_subreddit_names",
+	"show_domains",
+	"external_links_new_tab",
+	"hide_seen_posts",
+	"hide_comment_scores",
+	"strip_tracking_params",
+	"score_format",
+	"show_author",
+	"ui_density",
+	"collapse_long_quotes",
+	"show_reading_time",
+	"lang",
 ];
 
 // FUNCTIONS
@@ -40,10 +74,19 @@ const PREFS: [&str; 13] = [
 // Retrieve cookies from request "Cookie" header
 pub async fn get(req: Request<Body>) -> Result<Response<Body>, String> {
 	let url = req.uri().to_string();
-	template(SettingsTemplate {
-		prefs: Preferences::new(&req),
-		url,
-	})
+	let imported = saved::LISTS
+		.iter()
+		.map(|&(name, file, route)| (name, file, route, saved::stored_ids(&req, name).len()))
+		.collect();
+	cache(
+		template(SettingsTemplate {
+			prefs: Preferences::new(&req),
+			imported,
+			seen: seen_posts(&req).len(),
+			url,
+		}),
+		CachePolicy::NoCache,
+	)
 }
 
 // Set cookies using response "Set-Cookie" header
@@ -86,9 +129,52 @@ pub async fn set(req: Request<Body>) -> Result<Response<Body>, String> {
 		};
 	}
 
+	// Per-subreddit sorts are typed in freely, so only keep valid entries
+	let sub_sorts = format_sub_sorts(&parse_sub_sorts(form.get("sub_sorts").map_or("", |value| value.as_ref())));
+	if sub_sorts.is_empty() {
+		response.remove_cookie("sub_sorts".to_string());
+	} else {
+		response.insert_cookie(
+			Cookie::build("sub_sorts", sub_sorts)
+				.path("/")
+				.http_only(true)
+				.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
+				.finish(),
+		);
+	}
+
+	// Custom feeds too, and they have to fit in a cookie
+	let feeds = format_feeds(&parse_feeds(form.get("feeds").map_or("", |value| value.as_ref())));
+	if feeds.len() > MAX_COOKIE_VALUE_LENGTH {
+		return error_with_status(
+			Request::from_parts(parts, Body::empty()),
+			400,
+			"Your custom feeds are too long to save in a cookie. Remove some subreddits from them.",
+		)
+		.await;
+	} else if feeds.is_empty() {
+		response.remove_cookie("feeds".to_string());
+	} else {
+		response.insert_cookie(
+			Cookie::build("feeds", feeds)
+				.path("/")
+				.http_only(true)
+				.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
+				.finish(),
+		);
+	}
+
 	Ok(response)
 }
 
+// Where to send the user after changing settings from another page
+fn redirect_path(form: &HashMap<Cow<str>, Cow<str>>) -> String {
+	match form.get("redirect") {
+		Some(value) => format!("/{}", value.replace("%26", "&").replace("%23", "#")),
+		None => "/".to_string(),
+	}
+}
+
 fn set_cookies_method(req: Request<Body>, remove_cookies: bool) -> Response<Body> {
 	// Split the body into parts
 	let (parts, _) = req.into_parts();
@@ -105,14 +191,9 @@ fn set_cookies_method(req: Request<Body>, remove_cookies: bool) -> Response<Body
 
 	let form = url::form_urlencoded::parse(query).collect::<HashMap<_, _>>();
 
-	let path = match form.get("redirect") {
-		Some(value) => format!("/{}", value.replace("%26", "&").replace("%23", "#")),
-		None => "/".to_string(),
-	};
+	let mut response = redirect(redirect_path(&form));
 
-	let mut response = redirect(path);
-
-	for name in [PREFS.to_vec(), vec!["subscriptions", "filters"]].concat() {
+	for name in [PREFS.to_vec(), vec!["subscriptions", "filters", "sub_sorts", "feeds"]].concat() {
 		match form.get(name) {
 			Some(value) => response.insert_cookie(
 				Cookie::build(name.to_owned(), value.clone())
@@ -140,3 +221,38 @@ pub async fn restore(req: Request<Body>) -> Result<Response<Body>, String> {
 pub async fn update(req: Request<Body>) -> Result<Response<Body>, String> {
 	Ok(set_cookies_method(req, false))
 }
+
+// Mark the posts in `ids` as seen, or forget every seen post, then go back
+pub async fn seen(req: Request<Body>) -> Result<Response<Body>, String> {
+	let form = url::form_urlencoded::parse(req.uri().query().unwrap_or_default().as_bytes()).collect::<HashMap<_, _>>();
+	let mut response = redirect(redirect_path(&form));
+
+	if req.uri().path().ends_with("/clear") {
+		response.remove_cookie("seen".to_string());
+		return Ok(response);
+	}
+
+	let mut ids = seen_posts(&req);
+	for id in form.get("ids").map_or("", |ids| ids.as_ref()).split(',') {
+		let id = id.trim().to_lowercase();
+		if !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()) && !ids.contains(&id) {
+			ids.push(id);
+		}
+	}
+
+	// Forget the posts seen longest ago once the list outgrows its cookie
+	let mut value = ids.join("+");
+	while value.len() > MAX_COOKIE_VALUE_LENGTH {
+		value = value.split_once('+').map_or(String::new(), |(_, rest)| rest.to_string());
+	}
+
+	response.insert_cookie(
+		Cookie::build("seen", value)
+			.path("/")
+			.http_only(true)
+			.expires(OffsetDateTime::now_utc() + Duration::weeks(52))
+			.finish(),
+	);
+
+	Ok(response)
+}
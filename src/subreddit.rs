@@ -1,11 +1,13 @@
 // CRATES
 use crate::utils::{
-	catch_random, error, filter_posts, format_num, format_url, get_filters, nsfw_landing, param, redirect, rewrite_urls, setting, template, val, Post, Preferences, Subreddit,
+	cache, catch_random, debug_json, debug_response, error, error_with_status, error_with_suggestions, filter_posts, format_num, format_url, get_filters, nsfw_landing, param,
+	redirect, rewrite_urls, seen_posts, setting, sfw_only, subscriptions_enabled, template, val, CachePolicy, Post, Preferences, Subreddit,
 };
-use crate::{client::json, server::ResponseExt, RequestExt};
+use crate::{client::json, server::ResponseExt, utils, RequestExt};
 use askama::Template;
 use cookie::Cookie;
 use hyper::{Body, Request, Response};
+use once_cell::sync::OnceCell;
 use time::{Duration, OffsetDateTime};
 
 // STRUCTS
@@ -27,6 +29,10 @@ struct SubredditTemplate {
 	/// Whether all posts were hidden because they are NSFW (and user has disabled show NSFW)
 	all_posts_hidden_nsfw: bool,
 	no_posts: bool,
+	/// Region passed to Reddit as `geo_filter`, or empty if none was given.
+	geo: String,
+	/// Whether the region selector should be shown (front page only).
+	show_geo: bool,
 }
 
 #[derive(Template)]
@@ -35,6 +41,43 @@ struct WikiTemplate {
 	sub: String,
 	wiki: String,
 	page: String,
+	/// Author of the page's latest revision
+	revision_by: String,
+	/// Relative and absolute time of the page's latest revision
+	revision_date: (String, String),
+	/// The past revision shown instead of the current page, if one was asked for
+	revision: String,
+	prefs: Preferences,
+	url: String,
+}
+
+#[derive(Template)]
+#[template(path = "revisions.html")]
+struct RevisionsTemplate {
+	sub: String,
+	page: String,
+	/// Revisions, newest first, as (ID, author, relative and absolute time, reason)
+	revisions: Vec<(String, String, (String, String), String)>,
+	prefs: Preferences,
+	url: String,
+}
+
+#[derive(Template)]
+#[template(path = "moderators.html")]
+struct ModeratorsTemplate {
+	sub: String,
+	/// Moderators by name, with the relative and absolute time they were added
+	moderators: Vec<(String, (String, String))>,
+	prefs: Preferences,
+	url: String,
+}
+
+#[derive(Template)]
+#[template(path = "rules.html")]
+struct RulesTemplate {
+	sub: String,
+	/// Rules by title, with their description as HTML
+	rules: Vec<(String, String)>,
 	prefs: Preferences,
 	url: String,
 }
@@ -49,13 +92,115 @@ struct WallTemplate {
 	url: String,
 }
 
+// CONSTANTS
+
+/// Region codes Reddit accepts for the `geo_filter` listing parameter, paired
+/// with a human-readable name for the region selector.
+pub const GEO_FILTERS: [(&str, &str); 36] = [
+	("GLOBAL", "Everywhere"),
+	("AR", "Argentina"),
+	("AU", "Australia"),
+	("BG", "Bulgaria"),
+	("CA", "Canada"),
+	("CL", "Chile"),
+	("CO", "Colombia"),
+	("HR", "Croatia"),
+	("CZ", "Czech Republic"),
+	("FI", "Finland"),
+	("FR", "France"),
+	("DE", "Germany"),
+	("GR", "Greece"),
+	("HU", "Hungary"),
+	("IS", "Iceland"),
+	("IN", "India"),
+	("IE", "Ireland"),
+	("IT", "Italy"),
+	("JP", "Japan"),
+	("MY", "Malaysia"),
+	("MX", "Mexico"),
+	("NZ", "New Zealand"),
+	("PH", "Philippines"),
+	("PL", "Poland"),
+	("PT", "Portugal"),
+	("PR", "Puerto Rico"),
+	("RO", "Romania"),
+	("RS", "Serbia"),
+	("SG", "Singapore"),
+	("ES", "Spain"),
+	("SE", "Sweden"),
+	("TW", "Taiwan"),
+	("TH", "Thailand"),
+	("TR", "Turkey"),
+	("GB", "United Kingdom"),
+	("US", "United States"),
+];
+
+/// Sorts for which Reddit honors `geo_filter`.
+const GEO_FILTER_SORTS: [&str; 3] = ["best", "hot", "rising"];
+
+/// Returns the normalized region code if `geo` is one Reddit accepts.
+fn geo_filter(geo: &str) -> Option<&'static str> {
+	GEO_FILTERS.iter().map(|(code, _)| *code).find(|code| code.eq_ignore_ascii_case(geo))
+}
+
 // SERVICES
+/// Shows one of the user's named custom feeds: the subreddits in it as a
+/// multireddit.
+pub async fn feed(req: Request<Body>) -> Result<Response<Body>, String> {
+	let name = req.param("name").unwrap_or_default().to_lowercase();
+	let Some((_, subs)) = Preferences::new(&req).feeds.into_iter().find(|(feed, _)| *feed == name) else {
+		return error_with_status(req, 404, format!("You don't have a feed named \"{}\". Custom feeds are made in settings.", name)).await;
+	};
+
+	combined(req, &subs).await
+}
+
+/// Shows a user's public multireddit, such as /user/spez/m/tech.
+pub async fn multireddit(req: Request<Body>) -> Result<Response<Body>, String> {
+	let user = req.param("name").unwrap_or_default();
+	let multi = req.param("multi").unwrap_or_default();
+	let valid = |name: &str| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+	if !valid(&user) || !valid(&multi) {
+		return error_with_status(req, 404, "That isn't a multireddit").await;
+	}
+
+	let path = format!("/api/multi/user/{}/m/{}?raw_json=1", user, multi);
+	let subs: Vec<String> = match json(path, false).await {
+		Ok(response) => response["data"]["subreddits"]
+			.as_array()
+			.unwrap_or(&Vec::new())
+			.iter()
+			.filter_map(|sub| sub["name"].as_str().map(String::from))
+			.collect(),
+		// Reddit answers the same for private multireddits as for ones that don't exist
+		Err(e) if matches!(e.status, 403 | 404) => {
+			return error_with_status(req, 404, format!("u/{} has no public multireddit named m/{}", user, multi)).await;
+		}
+		Err(msg) => return error(req, msg).await,
+	};
+
+	if subs.is_empty() {
+		return error_with_status(req, 404, format!("u/{}'s multireddit m/{} has no subreddits in it", user, multi)).await;
+	}
+
+	combined(req, &subs).await
+}
+
+// Shows the posts of several subreddits together, as /r/a+b would
+async fn combined(mut req: Request<Body>, subs: &[String]) -> Result<Response<Body>, String> {
+	let mut params = req.params();
+	params.insert("sub".to_string(), subs.join("+"));
+	req.set_params(params);
+
+	community(req).await
+}
+
 pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 	// Build Reddit API path
 	let root = req.uri().path() == "/";
 	let subscribed = setting(&req, "subscriptions");
 	let front_page = setting(&req, "front_page");
-	let post_sort = req.cookie("post_sort").map_or_else(|| "hot".to_string(), |c| c.value().to_string());
+	let post_sort = default_sort(&req);
 	let sort = req.param("sort").unwrap_or_else(|| req.param("id").unwrap_or(post_sort));
 
 	let sub_name = req.param("sub").unwrap_or(if front_page == "default" || front_page.is_empty() {
@@ -78,10 +223,23 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 		return Ok(redirect(["/user/", &sub_name[2..]].concat()));
 	}
 
+	// Apply the user's default sort for this subreddit unless another was requested
+	if req.param("sub").is_some() && req.param("sort").is_none() && req.uri().query().is_none() {
+		let prefs = Preferences::new(&req);
+		if let Some((_, sort, timeframe)) = prefs.sub_sorts.iter().find(|(sub, _, _)| sub.eq_ignore_ascii_case(&sub_name)) {
+			let timeframe = if timeframe.is_empty() { String::new() } else { format!("?t={}", timeframe) };
+			return Ok(redirect(format!("/r/{}/{}{}", sub_name, sort, timeframe)));
+		}
+	}
+
 	// Request subreddit metadata
-	let sub = if !sub_name.contains('+') && sub_name != subscribed && sub_name != "popular" && sub_name != "all" {
+	let sub = if !sub_name.contains('+') && sub_name != subscribed && !is_pseudo_subreddit(&sub_name) {
 		// Regular subreddit
-		subreddit(&sub_name, quarantined).await.unwrap_or_default()
+		match subreddit(&sub_name, quarantined).await {
+			Ok(sub) => sub,
+			Err(msg) if msg == "Not Found" => return not_found(req, &sub_name).await,
+			Err(_) => Subreddit::default(),
+		}
 	} else if sub_name == subscribed {
 		// Subscription feed
 		if req.uri().path().starts_with("/r/") {
@@ -89,8 +247,14 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 		} else {
 			Subreddit::default()
 		}
+	} else if is_pseudo_subreddit(&sub_name) {
+		// All, popular
+		Subreddit {
+			name: sub_name.to_lowercase(),
+			..Subreddit::default()
+		}
 	} else {
-		// Multireddit, all, popular
+		// Multireddit
 		Subreddit {
 			name: sub_name.clone(),
 			..Subreddit::default()
@@ -104,60 +268,155 @@ pub async fn community(req: Request<Body>) -> Result<Response<Body>, String> {
 		return Ok(nsfw_landing(req, req_url).await.unwrap_or_default());
 	}
 
-	let path = format!("/r/{}/{}.json?{}&raw_json=1", sub_name.clone(), sort, req.uri().query().unwrap_or_default());
+	let mut path = format!("/r/{}/{}.json?{}&raw_json=1", sub_name.clone(), sort, req.uri().query().unwrap_or_default());
+	// Listings mixing subreddits show each post's subreddit icon, which Reddit
+	// can send along with the posts
+	if sub_name.contains('+') || is_pseudo_subreddit(&sub_name) {
+		path.push_str("&sr_detail=1");
+	}
 	let url = String::from(req.uri().path_and_query().map_or("", |val| val.as_str()));
 	let redirect_url = url[1..].replace('?', "%3F").replace('&', "%26").replace('+', "%2B");
 	let filters = get_filters(&req);
 
+	// Pass a valid region on to Reddit for sorts that support it; ignore anything else
+	let geo = match param(&path, "geo").as_deref().and_then(geo_filter) {
+		Some(code) if GEO_FILTER_SORTS.contains(&sort.as_str()) => {
+			path.push_str(&format!("&geo_filter={}", code));
+			code.to_string()
+		}
+		_ => String::new(),
+	};
+	let show_geo = req.param("sub").is_none() && GEO_FILTER_SORTS.contains(&sort.as_str());
+
+	if debug_json(&req) {
+		return debug_response(&path, quarantined).await;
+	}
+
 	// If all requested subs are filtered, we don't need to fetch posts.
 	if sub_name.split('+').all(|s| filters.contains(s)) {
-		template(SubredditTemplate {
-			sub,
-			posts: Vec::new(),
-			sort: (sort, param(&path, "t").unwrap_or_default()),
-			ends: (param(&path, "after").unwrap_or_default(), "".to_string()),
-			prefs: Preferences::new(&req),
-			url,
-			redirect_url,
-			is_filtered: true,
-			all_posts_filtered: false,
-			all_posts_hidden_nsfw: false,
-			no_posts: false,
-		})
+		cache(
+			template(SubredditTemplate {
+				sub,
+				posts: Vec::new(),
+				sort: (sort, param(&path, "t").unwrap_or_default()),
+				ends: (param(&path, "after").unwrap_or_default(), "".to_string()),
+				prefs: Preferences::new(&req),
+				url,
+				redirect_url,
+				is_filtered: true,
+				all_posts_filtered: false,
+				all_posts_hidden_nsfw: false,
+				no_posts: false,
+				geo,
+				show_geo,
+			}),
+			CachePolicy::Listing,
+		)
 	} else {
 		match Post::fetch(&path, quarantined).await {
 			Ok((mut posts, after)) => {
 				let (_, all_posts_filtered) = filter_posts(&mut posts, &filters);
+				if setting(&req, "hide_seen_posts") == "on" {
+					let seen = seen_posts(&req);
+					posts.retain(|post| !seen.contains(&post.id));
+				}
 				let no_posts = posts.is_empty();
 				let all_posts_hidden_nsfw = !no_posts && (posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on");
-				template(SubredditTemplate {
-					sub,
-					posts,
-					sort: (sort, param(&path, "t").unwrap_or_default()),
-					ends: (param(&path, "after").unwrap_or_default(), after),
-					prefs: Preferences::new(&req),
-					url,
-					redirect_url,
-					is_filtered: false,
-					all_posts_filtered,
-					all_posts_hidden_nsfw,
-					no_posts,
-				})
+				cache(
+					template(SubredditTemplate {
+						sub,
+						posts,
+						sort: (sort, param(&path, "t").unwrap_or_default()),
+						ends: (param(&path, "after").unwrap_or_default(), after),
+						prefs: Preferences::new(&req),
+						url,
+						redirect_url,
+						is_filtered: false,
+						all_posts_filtered,
+						all_posts_hidden_nsfw,
+						no_posts,
+						geo,
+						show_geo,
+					}),
+					CachePolicy::Listing,
+				)
 			}
-			Err(msg) => match msg.as_str() {
-				"quarantined" | "gated" => quarantine(req, sub_name, msg),
-				"private" => error(req, format!("r/{} is a private community", sub_name)).await,
-				"banned" => error(req, format!("r/{} has been banned from Reddit", sub_name)).await,
-				_ => error(req, msg).await,
+			Err(e) => match e.msg.as_str() {
+				"quarantined" | "gated" => quarantine(req, sub_name, e.msg),
+				"over18" if !sfw_only() => quarantine(req, sub_name, e.msg),
+				"over18" => nsfw_landing(req, req_url).await,
+				"private" => error_with_status(req, 403, format!("This community is private: only approved members of r/{} can view it", sub_name)).await,
+				"banned" => error_with_status(req, 403, format!("This community has been banned: r/{} is no longer available on Reddit", sub_name)).await,
+				"Not Found" if !sub_name.contains('+') => not_found(req, &sub_name).await,
+				_ => error(req, e).await,
 			},
 		}
 	}
 }
 
+/// Renders a 404 for a subreddit that doesn't exist, suggesting similarly
+/// named ones from Reddit's subreddit search.
+async fn not_found(req: Request<Body>, sub: &str) -> Result<Response<Body>, String> {
+	let suggestions = similar_subreddits(sub).await;
+	error_with_suggestions(req, format!("r/{} doesn't exist", sub), suggestions).await
+}
+
+/// Searches Reddit for subreddits named like `sub`. Failures yield no suggestions.
+async fn similar_subreddits(sub: &str) -> Vec<String> {
+	// Subreddit names only contain letters, digits and underscores
+	let query: String = sub.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '_').collect();
+	if query.is_empty() {
+		return Vec::new();
+	}
+
+	let path = format!("/subreddits/search.json?q={}&limit=5&raw_json=1", query);
+	json(path, false).await.unwrap_or_default()["data"]["children"]
+		.as_array()
+		.map(|children| {
+			children
+				.iter()
+				.map(|child| val(child, "display_name"))
+				.filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case(sub))
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+/// Whether `sub` is one of Reddit's feeds that look like a subreddit but have
+/// no about page, sidebar or wiki: r/popular and r/all, including r/all with
+/// exclusions like r/all-pics.
+pub fn is_pseudo_subreddit(sub: &str) -> bool {
+	let sub = sub.to_lowercase();
+	sub == "all" || sub == "popular" || sub.starts_with("all-")
+}
+
 pub fn quarantine(req: Request<Body>, sub: String, restriction: String) -> Result<Response<Body>, String> {
+	let (title, msg) = if restriction == "over18" {
+		(
+			"This content is NSFW".to_string(),
+			format!(
+				"Reddit asks visitors to confirm they are over 18 before viewing this content from r/{}. Please click the button below to continue.",
+				sub
+			),
+		)
+	} else if restriction == "gated" {
+		(
+			"This community is gated".to_string(),
+			format!(
+				"Reddit asks visitors to confirm before viewing r/{}. Please click the button below to continue to this subreddit.",
+				sub
+			),
+		)
+	} else {
+		(
+			format!("r/{} is {}", sub, restriction),
+			"Please click the button below to continue to this subreddit.".to_string(),
+		)
+	};
+
 	let wall = WallTemplate {
-		title: format!("r/{} is {}", sub, restriction),
-		msg: "Please click the button below to continue to this subreddit.".to_string(),
+		title,
+		msg,
 		url: req.uri().to_string(),
 		sub,
 		prefs: Preferences::new(&req),
@@ -166,8 +425,8 @@ pub fn quarantine(req: Request<Body>, sub: String, restriction: String) -> Resul
 	Ok(
 		Response::builder()
 			.status(403)
-			.header("content-type", "text/html")
-			.body(wall.render().unwrap_or_default().into())
+			.header("content-type", utils::HTML_CONTENT_TYPE)
+			.body(utils::rebase_html(wall.render().unwrap_or_default()).into())
 			.unwrap_or_default(),
 	)
 }
@@ -177,7 +436,7 @@ pub async fn add_quarantine_exception(req: Request<Body>) -> Result<Response<Bod
 	let redir = param(&format!("?{}", req.uri().query().unwrap_or_default()), "redir").ok_or("Invalid URL")?;
 	let mut response = redirect(redir);
 	response.insert_cookie(
-		Cookie::build(&format!("allow_quaran_{}", subreddit.to_lowercase()), "true")
+		Cookie::build(format!("allow_quaran_{}", subreddit.to_lowercase()), "true")
 			.path("/")
 			.http_only(true)
 			.expires(cookie::Expiration::Session)
@@ -191,11 +450,39 @@ pub fn can_access_quarantine(req: &Request<Body>, sub: &str) -> bool {
 	setting(req, &format!("allow_quaran_{}", sub.to_lowercase())).parse().unwrap_or_default()
 }
 
+/// Sort for feeds without one in the URL, unless users choose their own. Set
+/// through `--default-sort` or the `LIBREDDIT_DEFAULT_POST_SORT` variable.
+pub static DEFAULT_SORT: OnceCell<String> = OnceCell::new();
+
+// The user's default sort, then the instance's, which can also come from
+// `LIBREDDIT_DEFAULT_POST_SORT` in libreddit.toml, then hot
+fn default_sort(req: &Request<Body>) -> String {
+	req
+		.cookie("post_sort")
+		.map(|cookie| cookie.value().to_string())
+		.or_else(|| DEFAULT_SORT.get().cloned())
+		.or_else(|| crate::config::get_setting("LIBREDDIT_DEFAULT_POST_SORT"))
+		.filter(|sort| !sort.is_empty())
+		.unwrap_or_else(|| "hot".to_string())
+}
+
+/// Most subreddits a user can subscribe to, set through `--max-subscriptions`.
+pub static MAX_SUBSCRIPTIONS: OnceCell<usize> = OnceCell::new();
+
+const DEFAULT_MAX_SUBSCRIPTIONS: usize = 250;
+
+// Browsers drop cookies bigger than 4096 bytes, name and attributes included
+pub const MAX_COOKIE_VALUE_LENGTH: usize = 3800;
+
 // Sub, filter, unfilter, or unsub by setting subscription cookie using response "Set-Cookie" header
 pub async fn subscriptions_filters(req: Request<Body>) -> Result<Response<Body>, String> {
 	let sub = req.param("sub").unwrap_or_default();
 	let action: Vec<String> = req.uri().path().split('/').map(String::from).collect();
 
+	if !subscriptions_enabled() && (action.contains(&"subscribe".to_string()) || action.contains(&"unsubscribe".to_string())) {
+		return error_with_status(req, 404, "Subscriptions are disabled on this instance").await;
+	}
+
 	// Handle random subreddits
 	if sub == "random" || sub == "randnsfw" {
 		if action.contains(&"filter".to_string()) || action.contains(&"unfilter".to_string()) {
@@ -205,6 +492,14 @@ pub async fn subscriptions_filters(req: Request<Body>) -> Result<Response<Body>,
 		}
 	}
 
+	if is_pseudo_subreddit(&sub) {
+		if action.contains(&"filter".to_string()) || action.contains(&"unfilter".to_string()) {
+			return Err(format!("Can't filter r/{}!", sub));
+		} else {
+			return Err(format!("Can't subscribe to r/{}!", sub));
+		}
+	}
+
 	let query = req.uri().query().unwrap_or_default().to_string();
 
 	let preferences = Preferences::new(&req);
@@ -244,6 +539,15 @@ pub async fn subscriptions_filters(req: Request<Body>) -> Result<Response<Body>,
 
 		// Modify sub list based on action
 		if action.contains(&"subscribe".to_string()) && !sub_list.contains(&part.to_owned()) {
+			let max = MAX_SUBSCRIPTIONS.get().copied().unwrap_or(DEFAULT_MAX_SUBSCRIPTIONS);
+			if sub_list.len() >= max {
+				return error_with_status(
+					req,
+					400,
+					format!("You can't subscribe to more than {} feeds. Unsubscribe from some in settings first.", max),
+				)
+				.await;
+			}
 			// Add each sub name to the subscribed list
 			sub_list.push(part.to_owned());
 			filters.retain(|s| s.to_lowercase() != part.to_lowercase());
@@ -274,6 +578,11 @@ pub async fn subscriptions_filters(req: Request<Body>) -> Result<Response<Body>,
 		format!("/r/{}", sub)
 	};
 
+	// Refuse to write a cookie the browser would silently throw away
+	if sub_list.join("+").len() > MAX_COOKIE_VALUE_LENGTH || filters.join("+").len() > MAX_COOKIE_VALUE_LENGTH {
+		return error_with_status(req, 400, "Your subscriptions or filters are too long to save in a cookie. Remove some in settings first.").await;
+	}
+
 	let mut response = redirect(path);
 
 	// Delete cookie if empty, else set
@@ -311,25 +620,97 @@ pub async fn wiki(req: Request<Body>) -> Result<Response<Body>, String> {
 		return Ok(random);
 	}
 
+	if is_pseudo_subreddit(&sub) {
+		return error_with_status(req, 404, format!("r/{} doesn't have a wiki", sub)).await;
+	}
+
 	let page = req.param("page").unwrap_or_else(|| "index".to_string());
-	let path: String = format!("/r/{}/wiki/{}.json?raw_json=1", sub, page);
+	let mut path: String = format!("/r/{}/wiki/{}.json?raw_json=1", sub, page);
 	let url = req.uri().to_string();
 
+	// Show a past revision of the page
+	let revision = param(&url, "v").unwrap_or_default();
+	if !revision.is_empty() {
+		if !is_revision_id(&revision) {
+			return error_with_status(req, 400, format!("\"{}\" isn't a wiki revision", revision)).await;
+		}
+		path.push_str(&format!("&v={}", revision));
+	}
+
 	match json(path, quarantined).await {
-		Ok(response) => template(WikiTemplate {
-			sub,
-			wiki: rewrite_urls(response["data"]["content_html"].as_str().unwrap_or("<h3>Wiki not found</h3>")),
-			page,
-			prefs: Preferences::new(&req),
-			url,
-		}),
-		Err(msg) => {
-			if msg == "quarantined" || msg == "gated" {
-				quarantine(req, sub, msg)
-			} else {
-				error(req, msg).await
-			}
+		Ok(response) => cache(
+			template(WikiTemplate {
+				sub,
+				wiki: rewrite_urls(response["data"]["content_html"].as_str().unwrap_or("<h3>Wiki not found</h3>")),
+				page,
+				revision_by: response["data"]["revision_by"]["data"]["name"].as_str().unwrap_or_default().to_string(),
+				revision_date: response["data"]["revision_date"].as_f64().map_or((String::new(), String::new()), utils::time),
+				revision,
+				prefs: Preferences::new(&req),
+				url,
+			}),
+			CachePolicy::About,
+		),
+		Err(e) => match e.msg.as_str() {
+			"quarantined" | "gated" => quarantine(req, sub, e.msg),
+			"Not Found" if !revision.is_empty() => error_with_status(req, 404, format!("This wiki page has no revision {}", revision)).await,
+			_ => error(req, e).await,
+		},
+	}
+}
+
+// Whether `id` looks like the UUID Reddit gives each wiki revision
+fn is_revision_id(id: &str) -> bool {
+	id.len() == 36
+		&& id
+			.chars()
+			.enumerate()
+			.all(|(i, c)| if matches!(i, 8 | 13 | 18 | 23) { c == '-' } else { c.is_ascii_hexdigit() })
+}
+
+pub async fn wiki_revisions(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub = req.param("sub").unwrap_or_else(|| "reddit.com".to_string());
+	let quarantined = can_access_quarantine(&req, &sub);
+
+	if is_pseudo_subreddit(&sub) {
+		return error_with_status(req, 404, format!("r/{} doesn't have a wiki", sub)).await;
+	}
+
+	let page = req.param("page").unwrap_or_else(|| "index".to_string());
+	let path: String = format!("/r/{}/wiki/revisions/{}.json?raw_json=1&limit=100", sub, page);
+	let url = req.uri().to_string();
+
+	match json(path, quarantined).await {
+		Ok(response) => {
+			let revisions = response["data"]["children"]
+				.as_array()
+				.unwrap_or(&Vec::new())
+				.iter()
+				.filter_map(|revision| {
+					let id = revision["id"].as_str().filter(|id| is_revision_id(id))?;
+					let author = revision["author"]["data"]["name"].as_str().unwrap_or_default().to_string();
+					let date = revision["timestamp"].as_f64().map_or((String::new(), String::new()), utils::time);
+					let reason = revision["reason"].as_str().unwrap_or_default().to_string();
+					Some((id.to_string(), author, date, reason))
+				})
+				.collect();
+
+			cache(
+				template(RevisionsTemplate {
+					sub,
+					page,
+					revisions,
+					prefs: Preferences::new(&req),
+					url,
+				}),
+				CachePolicy::About,
+			)
 		}
+		Err(e) => match e.msg.as_str() {
+			"quarantined" | "gated" => quarantine(req, sub, e.msg),
+			"Forbidden" => error_with_status(req, 403, format!("The history of this page of r/{}'s wiki isn't public", sub)).await,
+			_ => error(req, e).await,
+		},
 	}
 }
 
@@ -342,6 +723,10 @@ pub async fn sidebar(req: Request<Body>) -> Result<Response<Body>, String> {
 		return Ok(random);
 	}
 
+	if is_pseudo_subreddit(&sub) {
+		return error_with_status(req, 404, format!("r/{} doesn't have a sidebar", sub)).await;
+	}
+
 	// Build the Reddit JSON API url
 	let path: String = format!("/r/{}/about.json?raw_json=1", sub);
 	let url = req.uri().to_string();
@@ -349,61 +734,130 @@ pub async fn sidebar(req: Request<Body>) -> Result<Response<Body>, String> {
 	// Send a request to the url
 	match json(path, quarantined).await {
 		// If success, receive JSON in response
-		Ok(response) => template(WikiTemplate {
-			wiki: rewrite_urls(&val(&response, "description_html")),
-			// wiki: format!(
-			// 	"{}<hr><h1>Moderators</h1><br><ul>{}</ul>",
-			// 	rewrite_urls(&val(&response, "description_html"),
-			// 	moderators(&sub, quarantined).await.unwrap_or(vec!["Could not fetch moderators".to_string()]).join(""),
-			// ),
-			sub,
-			page: "Sidebar".to_string(),
-			prefs: Preferences::new(&req),
-			url,
-		}),
-		Err(msg) => {
-			if msg == "quarantined" || msg == "gated" {
-				quarantine(req, sub, msg)
+		Ok(response) => cache(
+			template(WikiTemplate {
+				wiki: rewrite_urls(&val(&response, "description_html")),
+				sub,
+				page: "Sidebar".to_string(),
+				revision_by: String::new(),
+				revision_date: (String::new(), String::new()),
+				revision: String::new(),
+				prefs: Preferences::new(&req),
+				url,
+			}),
+			CachePolicy::About,
+		),
+		Err(e) => {
+			if e.msg == "quarantined" || e.msg == "gated" {
+				quarantine(req, sub, e.msg)
 			} else {
-				error(req, msg).await
+				error(req, e).await
 			}
 		}
 	}
 }
 
-// pub async fn moderators(sub: &str, quarantined: bool) -> Result<Vec<String>, String> {
-// 	// Retrieve and format the html for the moderators list
-// 	Ok(
-// 		moderators_list(sub, quarantined)
-// 			.await?
-// 			.iter()
-// 			.map(|m| format!("<li><a style=\"color: var(--accent)\" href=\"/u/{name}\">{name}</a></li>", name = m))
-// 			.collect(),
-// 	)
-// }
-
-// async fn moderators_list(sub: &str, quarantined: bool) -> Result<Vec<String>, String> {
-// 	// Build the moderator list URL
-// 	let path: String = format!("/r/{}/about/moderators.json?raw_json=1", sub);
-
-// 	// Retrieve response
-// 	json(path, quarantined).await.map(|response| {
-// 		// Traverse json tree and format into list of strings
-// 		response["data"]["children"]
-// 			.as_array()
-// 			.unwrap_or(&Vec::new())
-// 			.iter()
-// 			.filter_map(|moderator| {
-// 				let name = moderator["name"].as_str().unwrap_or_default();
-// 				if name.is_empty() {
-// 					None
-// 				} else {
-// 					Some(name.to_string())
-// 				}
-// 			})
-// 			.collect::<Vec<_>>()
-// 	})
-// }
+pub async fn moderators(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub = req.param("sub").unwrap_or_default();
+	let quarantined = can_access_quarantine(&req, &sub);
+
+	// Handle random subreddits
+	if let Ok(random) = catch_random(&sub, "/about/moderators").await {
+		return Ok(random);
+	}
+
+	if is_pseudo_subreddit(&sub) {
+		return error_with_status(req, 404, format!("r/{} doesn't have moderators", sub)).await;
+	}
+
+	let path: String = format!("/r/{}/about/moderators.json?raw_json=1", sub);
+	let url = req.uri().to_string();
+
+	match json(path, quarantined).await {
+		Ok(response) => {
+			// Traverse json tree and collect the moderators and when they were added
+			let moderators = response["data"]["children"]
+				.as_array()
+				.unwrap_or(&Vec::new())
+				.iter()
+				.filter_map(|moderator| {
+					let name = moderator["name"].as_str().filter(|name| !name.is_empty())?;
+					let added = moderator["date"].as_f64().map_or((String::new(), String::new()), utils::time);
+					Some((name.to_string(), added))
+				})
+				.collect();
+
+			cache(
+				template(ModeratorsTemplate {
+					sub,
+					moderators,
+					prefs: Preferences::new(&req),
+					url,
+				}),
+				CachePolicy::About,
+			)
+		}
+		Err(e) => match e.msg.as_str() {
+			"quarantined" | "gated" => quarantine(req, sub, e.msg),
+			"Forbidden" => error_with_status(req, 403, format!("The moderators of r/{} aren't public", sub)).await,
+			_ => error(req, e).await,
+		},
+	}
+}
+
+pub async fn rules(req: Request<Body>) -> Result<Response<Body>, String> {
+	let sub = req.param("sub").unwrap_or_default();
+	let quarantined = can_access_quarantine(&req, &sub);
+
+	// Handle random subreddits
+	if let Ok(random) = catch_random(&sub, "/about/rules").await {
+		return Ok(random);
+	}
+
+	if is_pseudo_subreddit(&sub) {
+		return error_with_status(req, 404, format!("r/{} doesn't have rules", sub)).await;
+	}
+
+	let path: String = format!("/r/{}/about/rules.json?raw_json=1", sub);
+	let url = req.uri().to_string();
+
+	match json(path, quarantined).await {
+		Ok(response) => {
+			// Rules are listed in the order moderators gave them
+			let rules = response["rules"]
+				.as_array()
+				.unwrap_or(&Vec::new())
+				.iter()
+				.filter_map(|rule| {
+					let title = rule["short_name"].as_str().filter(|title| !title.is_empty())?;
+					let description = rewrite_urls(rule["description_html"].as_str().unwrap_or_default());
+					Some((title.to_string(), description))
+				})
+				.collect();
+
+			cache(
+				template(RulesTemplate {
+					sub,
+					rules,
+					prefs: Preferences::new(&req),
+					url,
+				}),
+				CachePolicy::About,
+			)
+		}
+		Err(e) => match e.msg.as_str() {
+			"quarantined" | "gated" => quarantine(req, sub, e.msg),
+			_ => error(req, e).await,
+		},
+	}
+}
+
+// Whether a string is a CSS hex color like "#0079d3"
+fn is_hex_color(color: &str) -> bool {
+	color
+		.strip_prefix('#')
+		.is_some_and(|hex| matches!(hex.len(), 3 | 6 | 8) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
 
 // SUBREDDIT
 async fn subreddit(sub: &str, quarantined: bool) -> Result<Subreddit, String> {
@@ -413,6 +867,11 @@ async fn subreddit(sub: &str, quarantined: bool) -> Result<Subreddit, String> {
 	// Send a request to the url
 	let res = json(path, quarantined).await?;
 
+	// Reddit redirects the about page of a nonexistent subreddit to a subreddit search
+	if res["kind"] != "t5" {
+		return Err("Not Found".to_string());
+	}
+
 	// Metadata regarding the subreddit
 	let members: i64 = res["data"]["subscribers"].as_u64().unwrap_or_default() as i64;
 	let active: i64 = res["data"]["accounts_active"].as_u64().unwrap_or_default() as i64;
@@ -421,13 +880,28 @@ async fn subreddit(sub: &str, quarantined: bool) -> Result<Subreddit, String> {
 	let community_icon: &str = res["data"]["community_icon"].as_str().unwrap_or_default();
 	let icon = if community_icon.is_empty() { val(&res, "icon_img") } else { community_icon.to_string() };
 
+	// Fetch subreddit banner either from the banner_background_image or banner_img value
+	let background_image: &str = res["data"]["banner_background_image"].as_str().unwrap_or_default();
+	let banner = if background_image.is_empty() {
+		val(&res, "banner_img")
+	} else {
+		background_image.to_string()
+	};
+
+	// Only accept hex colors so the value is safe to put in a style attribute
+	let banner_color = [val(&res, "banner_background_color"), val(&res, "key_color")]
+		.into_iter()
+		.find(|color| is_hex_color(color))
+		.unwrap_or_default();
+
 	Ok(Subreddit {
 		name: val(&res, "display_name"),
 		title: val(&res, "title"),
 		description: val(&res, "public_description"),
 		info: rewrite_urls(&val(&res, "description_html")),
-		// moderators: moderators_list(sub, quarantined).await.unwrap_or_default(),
 		icon: format_url(&icon),
+		banner: format_url(&banner),
+		banner_color,
 		members: format_num(members),
 		active: format_num(active),
 		wiki: res["data"]["wiki_enabled"].as_bool().unwrap_or_default(),
@@ -1,9 +1,14 @@
 // CRATES
-use crate::client::json;
+use crate::client::{json, FetchError};
 use crate::server::RequestExt;
-use crate::utils::{error, filter_posts, format_url, get_filters, nsfw_landing, param, setting, template, Post, Preferences, User};
+use crate::utils::{
+	cache, debug_json, debug_response, error, filter_posts, format_num, format_url, get_filters, nsfw_landing, param, setting, template, CachePolicy, Post, Preferences, Trophy,
+	User,
+};
 use askama::Template;
+use futures_lite::future::zip;
 use hyper::{Body, Request, Response};
+use serde_json::Value;
 use time::{macros::format_description, OffsetDateTime};
 
 // STRUCTS
@@ -58,22 +63,29 @@ pub async fn profile(req: Request<Body>) -> Result<Response<Body>, String> {
 		return Ok(nsfw_landing(req, req_url).await.unwrap_or_default());
 	}
 
+	if debug_json(&req) {
+		return debug_response(&path, false).await;
+	}
+
 	let filters = get_filters(&req);
 	if filters.contains(&["u_", &username].concat()) {
-		template(UserTemplate {
-			user,
-			posts: Vec::new(),
-			sort: (sort, param(&path, "t").unwrap_or_default()),
-			ends: (param(&path, "after").unwrap_or_default(), "".to_string()),
-			listing,
-			prefs: Preferences::new(&req),
-			url,
-			redirect_url,
-			is_filtered: true,
-			all_posts_filtered: false,
-			all_posts_hidden_nsfw: false,
-			no_posts: false,
-		})
+		cache(
+			template(UserTemplate {
+				user,
+				posts: Vec::new(),
+				sort: (sort, param(&path, "t").unwrap_or_default()),
+				ends: (param(&path, "after").unwrap_or_default(), "".to_string()),
+				listing,
+				prefs: Preferences::new(&req),
+				url,
+				redirect_url,
+				is_filtered: true,
+				all_posts_filtered: false,
+				all_posts_hidden_nsfw: false,
+				no_posts: false,
+			}),
+			CachePolicy::Listing,
+		)
 	} else {
 		// Request user posts/comments from Reddit
 		match Post::fetch(&path, false).await {
@@ -81,20 +93,23 @@ pub async fn profile(req: Request<Body>) -> Result<Response<Body>, String> {
 				let (_, all_posts_filtered) = filter_posts(&mut posts, &filters);
 				let no_posts = posts.is_empty();
 				let all_posts_hidden_nsfw = !no_posts && (posts.iter().all(|p| p.flags.nsfw) && setting(&req, "show_nsfw") != "on");
-				template(UserTemplate {
-					user,
-					posts,
-					sort: (sort, param(&path, "t").unwrap_or_default()),
-					ends: (param(&path, "after").unwrap_or_default(), after),
-					listing,
-					prefs: Preferences::new(&req),
-					url,
-					redirect_url,
-					is_filtered: false,
-					all_posts_filtered,
-					all_posts_hidden_nsfw,
-					no_posts,
-				})
+				cache(
+					template(UserTemplate {
+						user,
+						posts,
+						sort: (sort, param(&path, "t").unwrap_or_default()),
+						ends: (param(&path, "after").unwrap_or_default(), after),
+						listing,
+						prefs: Preferences::new(&req),
+						url,
+						redirect_url,
+						is_filtered: false,
+						all_posts_filtered,
+						all_posts_hidden_nsfw,
+						no_posts,
+					}),
+					CachePolicy::Listing,
+				)
 			}
 			// If there is an error show error page
 			Err(msg) => error(req, msg).await,
@@ -103,12 +118,17 @@ pub async fn profile(req: Request<Body>) -> Result<Response<Body>, String> {
 }
 
 // USER
-async fn user(name: &str) -> Result<User, String> {
-	// Build the Reddit JSON API path
+async fn user(name: &str) -> Result<User, FetchError> {
+	// Build the Reddit JSON API paths
 	let path: String = format!("/user/{}/about.json?raw_json=1", name);
+	let trophies_path: String = format!("/user/{}/trophies.json?raw_json=1", name);
+
+	// Send both requests at once. Trophies are optional, so a failure there
+	// shouldn't break the profile.
+	let (res, trophies) = zip(json(path, false), json(trophies_path, false)).await;
+	let trophies = trophies.map(|res| parse_trophies(&res)).unwrap_or_default();
 
-	// Send a request to the url
-	json(path, false).await.map(|res| {
+	res.map(|res| {
 		// Grab creation date as unix timestamp
 		let created_unix = res["data"]["created"].as_f64().unwrap_or(0.0).round() as i64;
 		let created = OffsetDateTime::from_unix_timestamp(created_unix).unwrap_or(OffsetDateTime::UNIX_EPOCH);
@@ -116,16 +136,60 @@ async fn user(name: &str) -> Result<User, String> {
 		// Closure used to parse JSON from Reddit APIs
 		let about = |item| res["data"]["subreddit"][item].as_str().unwrap_or_default().to_string();
 
+		// Users can hide their karma, in which case these are missing
+		let karma_breakdown = res["data"]["link_karma"]
+			.as_i64()
+			.zip(res["data"]["comment_karma"].as_i64())
+			.map(|(post, comment)| (format_num(post), format_num(comment)));
+
 		// Parse the JSON output into a User struct
 		User {
 			name: res["data"]["name"].as_str().unwrap_or(name).to_owned(),
 			title: about("title"),
 			icon: format_url(&about("icon_img")),
-			karma: res["data"]["total_karma"].as_i64().unwrap_or(0),
+			karma: format_num(res["data"]["total_karma"].as_i64().unwrap_or(0)),
+			karma_breakdown,
 			created: created.format(format_description!("[month repr:short] [day] '[year repr:last_two]")).unwrap_or_default(),
+			age: account_age(created, OffsetDateTime::now_utc()),
+			trophies,
 			banner: about("banner_img"),
 			description: about("public_description"),
 			nsfw: res["data"]["subreddit"]["over_18"].as_bool().unwrap_or_default(),
 		}
 	})
 }
+
+// Parse the trophies out of a TrophyList
+fn parse_trophies(res: &Value) -> Vec<Trophy> {
+	res["data"]["trophies"]
+		.as_array()
+		.map(|trophies| {
+			trophies
+				.iter()
+				.map(|trophy| {
+					let data = &trophy["data"];
+					Trophy {
+						name: data["name"].as_str().unwrap_or_default().to_string(),
+						description: data["description"].as_str().unwrap_or_default().to_string(),
+						icon: format_url(data["icon_70"].as_str().unwrap_or_default()),
+					}
+				})
+				.filter(|trophy| !trophy.name.is_empty())
+				.collect()
+		})
+		.unwrap_or_default()
+}
+
+// Human-readable age of an account created at `created`, e.g. "3 years"
+fn account_age(created: OffsetDateTime, now: OffsetDateTime) -> String {
+	let days = (now - created).whole_days().max(0);
+	let (count, unit) = if days >= 365 {
+		(days / 365, "year")
+	} else if days >= 30 {
+		(days / 30, "month")
+	} else {
+		(days, "day")
+	};
+
+	format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
@@ -2,18 +2,25 @@ use crate::config::get_setting;
 //
 // CRATES
 //
-use crate::{client::json, server::RequestExt};
+use crate::{
+	client::{json, FetchError},
+	server::RequestExt,
+};
 use askama::Template;
 use cookie::Cookie;
-use hyper::{Body, Request, Response};
-use once_cell::sync::Lazy;
-use regex::Regex;
+use hyper::{header, header::HeaderValue, Body, Request, Response};
+use once_cell::sync::{Lazy, OnceCell};
+use regex::{Captures, Regex};
 use rust_embed::RustEmbed;
 use serde_json::Value;
 use std::collections::{HashMap, HashSet};
 use std::env;
+use std::future::Future;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Mutex, PoisonError};
 use time::{macros::format_description, Duration, OffsetDateTime};
+use tokio::sync::watch;
 use url::Url;
 
 /// Write a message to stderr on debug mode. This function is a no-op on
@@ -126,6 +133,7 @@ impl Poll {
 }
 
 pub struct PollOption {
+	#[allow(dead_code)]
 	pub id: u64,
 	pub text: String,
 	pub vote_count: Option<u64>,
@@ -153,29 +161,97 @@ impl PollOption {
 	}
 }
 
+/// The post a crosspost was made from
+#[derive(Clone)]
+pub struct Crosspost {
+	pub community: String,
+	pub author: String,
+	pub permalink: String,
+	/// Whether the original post can still be viewed. When it can't, only
+	/// the fact that this is a crosspost is shown.
+	pub available: bool,
+}
+
+impl Crosspost {
+	/// The original post, if Reddit still shares what it contains
+	fn parent(data: &Value) -> Option<&Value> {
+		Some(&data["crosspost_parent_list"][0]).filter(|parent| parent.is_object() && Self::available(parent))
+	}
+
+	// Reddit keeps listing original posts whose subreddit went private or was
+	// banned, and posts that were removed, with most of their content stripped
+	fn available(parent: &Value) -> bool {
+		!matches!(parent["subreddit_type"].as_str(), Some("private" | "employees_only" | "gold_only")) && parent["removed_by_category"].is_null()
+	}
+
+	pub fn parse(data: &Value) -> Option<Self> {
+		match &data["crosspost_parent_list"][0] {
+			parent if parent.is_object() => Some(Self {
+				community: parent["subreddit"].as_str().unwrap_or_default().to_string(),
+				author: parent["author"].as_str().unwrap_or_default().to_string(),
+				permalink: parent["permalink"].as_str().unwrap_or_default().to_string(),
+				available: Self::available(parent),
+			}),
+			// Some crossposts of inaccessible posts only carry the parent's ID
+			_ => data["crosspost_parent"].as_str().map(|_| Self {
+				community: String::new(),
+				author: String::new(),
+				permalink: String::new(),
+				available: false,
+			}),
+		}
+	}
+}
+
 // Post flags with nsfw and stickied
 pub struct Flags {
 	pub nsfw: bool,
 	pub stickied: bool,
 }
 
+// Widest preview picked for images in data saver mode
+const LOW_RES_WIDTH: i64 = 640;
+
+// Widest preview picked for each image preview quality
+const PREVIEW_QUALITY_WIDTHS: [(&str, i64); 3] = [("low", 320), ("medium", 640), ("high", 1080)];
+
 #[derive(Debug)]
 pub struct Media {
 	pub url: String,
 	pub alt_url: String,
+	/// Smaller preview of images, used in data saver mode
+	pub low_res_url: String,
+	/// Smaller previews of images, as (width, URL) from narrowest to widest
+	pub resolutions: Vec<(i64, String)>,
 	pub width: i64,
 	pub height: i64,
 	pub poster: String,
 }
 
+// Whether a URL's path ends in the extension of a still image
+fn has_image_extension(url: &str) -> bool {
+	let path = url.split(['?', '#']).next().unwrap_or_default().to_ascii_lowercase();
+	[".jpg", ".jpeg", ".png", ".webp"].iter().any(|ext| path.ends_with(ext))
+}
+
 impl Media {
 	pub async fn parse(data: &Value) -> (String, Self, Vec<GalleryMedia>) {
 		let mut gallery = Vec::new();
 
+		// Crossposts only link to the original post, which has the media
+		let data = match Crosspost::parent(data) {
+			Some(parent) if !parent["is_self"].as_bool().unwrap_or_default() => parent,
+			_ => data,
+		};
+
 		// Define the various known places that Reddit might put video URLs.
 		let data_preview = &data["preview"]["reddit_video_preview"];
-		let secure_media = &data["secure_media"]["reddit_video"];
-		let crosspost_parent_media = &data["crosspost_parent_list"][0]["secure_media"]["reddit_video"];
+		let secure_media = if data["secure_media"]["reddit_video"].is_object() {
+			&data["secure_media"]["reddit_video"]
+		} else {
+			&data["media"]["reddit_video"]
+		};
+		let post_hint = data["post_hint"].as_str().unwrap_or_default();
 
 		// If post is a video, return the video
 		let (post_type, url_val, alt_url_val) = if data_preview["fallback_url"].is_string() {
@@ -190,14 +266,9 @@ impl Media {
 				&secure_media["fallback_url"],
 				Some(&secure_media["hls_url"]),
 			)
-		} else if crosspost_parent_media["fallback_url"].is_string() {
-			(
-				if crosspost_parent_media["is_gif"].as_bool().unwrap_or(false) { "gif" } else { "video" },
-				&crosspost_parent_media["fallback_url"],
-				Some(&crosspost_parent_media["hls_url"]),
-			)
-		} else if data["post_hint"].as_str().unwrap_or("") == "image" {
-			// Handle images, whether GIFs or pics
+		} else if post_hint == "image" {
+			// Handle images, whether GIFs or pics. Their URL often has no
+			// file extension, so Reddit's hint is what identifies them.
 			let preview = &data["preview"]["images"][0];
 			let mp4 = &preview["variants"]["mp4"];
 
@@ -206,7 +277,7 @@ impl Media {
 				("gif", &mp4["source"]["url"], None)
 			} else {
 				// Return the picture if the media is an image
-				if data["domain"] == "i.redd.it" {
+				if data["domain"] == "i.redd.it" || !preview["source"]["url"].is_string() {
 					("image", &data["url"], None)
 				} else {
 					("image", &preview["source"]["url"], None)
@@ -215,7 +286,7 @@ impl Media {
 		} else if data["is_self"].as_bool().unwrap_or_default() {
 			// If type is self, return permalink
 			("self", &data["permalink"], None)
-		} else if data["is_gallery"].as_bool().unwrap_or_default() {
+		} else if data["is_gallery"].as_bool().unwrap_or_default() || data["gallery_data"]["items"].is_array() {
 			// If this post contains a gallery of images
 			gallery = GalleryMedia::parse(&data["gallery_data"]["items"], &data["media_metadata"]);
 
@@ -223,8 +294,11 @@ impl Media {
 		} else if data["is_reddit_media_domain"].as_bool().unwrap_or_default() && data["domain"] == "i.redd.it" {
 			// If this post contains a reddit media (image) URL.
 			("image", &data["url"], None)
+		} else if post_hint.is_empty() && has_image_extension(data["url"].as_str().unwrap_or_default()) {
+			// Older posts and some crossposts have no hint, but obviously link an image
+			("image", &data["url"], None)
 		} else {
-			// If type can't be determined, return url
+			// Links, and embeds like rich:video that can't be played here
 			("link", &data["url"], None)
 		};
 
@@ -232,11 +306,32 @@ impl Media {
 
 		let alt_url = alt_url_val.map_or(String::new(), |val| format_url(val.as_str().unwrap_or_default()));
 
+		let resolutions: Vec<(i64, String)> = if post_type == "image" {
+			data["preview"]["images"][0]["resolutions"]
+				.as_array()
+				.into_iter()
+				.flatten()
+				.map(|res| (res["width"].as_i64().unwrap_or_default(), format_url(res["url"].as_str().unwrap_or_default())))
+				.filter(|(_, url)| !url.is_empty())
+				.collect()
+		} else {
+			Vec::new()
+		};
+
+		// Largest preview resolution that still fits a phone screen
+		let low_res_url = resolutions
+			.iter()
+			.rev()
+			.find(|(width, _)| *width <= LOW_RES_WIDTH)
+			.map_or(String::new(), |(_, url)| url.clone());
+
 		(
 			post_type.to_string(),
 			Self {
 				url: format_url(url_val.as_str().unwrap_or_default()),
 				alt_url,
+				low_res_url,
+				resolutions,
 				// Note: in the data["is_reddit_media_domain"] path above
 				// width and height will be 0.
 				width: source["width"].as_i64().unwrap_or_default(),
@@ -246,8 +341,24 @@ impl Media {
 			gallery,
 		)
 	}
+
+	/// The URL to show in listings: the static preview of an animated GIF
+	/// unless it should animate, or the widest preview allowed by `quality`
+	pub fn listing_url(&self, animate: bool, quality: &str) -> &str {
+		let is_gif = self.url.split('?').next().unwrap_or_default().ends_with(".gif");
+		if is_gif {
+			return if animate || self.poster.is_empty() { &self.url } else { &self.poster };
+		}
+
+		PREVIEW_QUALITY_WIDTHS
+			.iter()
+			.find(|(name, _)| *name == quality)
+			.and_then(|(_, max)| self.resolutions.iter().rev().find(|(width, _)| width <= max))
+			.map_or(&self.url, |(_, url)| url)
+	}
 }
 
+#[allow(dead_code)]
 pub struct GalleryMedia {
 	pub url: String,
 	pub width: i64,
@@ -306,7 +417,18 @@ pub struct Post {
 	pub domain: String,
 	pub rel_time: String,
 	pub created: String,
+	pub edited: (String, String),
 	pub num_duplicates: u64,
+	pub num_crossposts: u64,
+	pub crosspost: Option<Crosspost>,
+	/// Members of the post's subreddit, when the listing includes them
+	pub subscribers: Option<(String, String)>,
+	/// Icon of the post's subreddit, when the listing was fetched with
+	/// `sr_detail` to show where posts in it come from
+	pub community_icon: String,
+	/// Whether moderators put the post in contest mode, which shows its
+	/// comments in random order with their scores hidden
+	pub contest_mode: bool,
 	pub comments: (String, String),
 	pub gallery: Vec<GalleryMedia>,
 	pub awards: Awards,
@@ -315,19 +437,19 @@ pub struct Post {
 
 impl Post {
 	// Fetch posts of a user or subreddit and return a vector of posts and the "after" value
-	pub async fn fetch(path: &str, quarantine: bool) -> Result<(Vec<Self>, String), String> {
+	pub async fn fetch(path: &str, quarantine: bool) -> Result<(Vec<Self>, String), FetchError> {
 		// Send a request to the url
 		let res = match json(path.to_string(), quarantine).await {
 			// If success, receive JSON in response
 			Ok(response) => response,
 			// If the Reddit API returns an error, exit this function
-			Err(msg) => return Err(msg),
+			Err(e) => return Err(e),
 		};
 
 		// Fetch the list of posts from the JSON response
 		let post_list = match res["data"]["children"].as_array() {
 			Some(list) => list,
-			None => return Err("No posts found".to_string()),
+			None => return Err("No posts found".into()),
 		};
 
 		let mut posts: Vec<Self> = Vec::new();
@@ -337,6 +459,8 @@ impl Post {
 			let data = &post["data"];
 
 			let (rel_time, created) = time(data["created_utc"].as_f64().unwrap_or_default());
+			// Reddit sends `false` for posts that were never edited
+			let edited = data["edited"].as_f64().map_or((String::new(), String::new()), time);
 			let score = data["score"].as_i64().unwrap_or_default();
 			let ratio: f64 = data["upvote_ratio"].as_f64().unwrap_or(1.0) * 100.0;
 			let title = val(post, "title");
@@ -346,7 +470,7 @@ impl Post {
 			let awards = Awards::parse(&data["all_awardings"]);
 
 			// selftext_html is set for text posts when browsing.
-			let mut body = rewrite_urls(&val(post, "selftext_html"));
+			let mut body = rewrite_urls(&inline_media(&val(post, "selftext_html"), &data["media_metadata"]));
 			if body.is_empty() {
 				body = rewrite_urls(&val(post, "body_html"));
 			}
@@ -380,6 +504,8 @@ impl Post {
 				thumbnail: Media {
 					url: format_url(val(post, "thumbnail").as_str()),
 					alt_url: String::new(),
+					low_res_url: String::new(),
+					resolutions: Vec::new(),
 					width: data["thumbnail_width"].as_i64().unwrap_or_default(),
 					height: data["thumbnail_height"].as_i64().unwrap_or_default(),
 					poster: "".to_string(),
@@ -408,7 +534,13 @@ impl Post {
 				poll: Poll::parse(&data["poll_data"]),
 				rel_time,
 				created,
+				edited,
 				num_duplicates: post["data"]["num_duplicates"].as_u64().unwrap_or(0),
+				num_crossposts: data["num_crossposts"].as_u64().unwrap_or(0),
+				crosspost: Crosspost::parse(data),
+				subscribers: data["subreddit_subscribers"].as_i64().map(format_num),
+				community_icon: community_icon(&data["sr_detail"]),
+				contest_mode: data["contest_mode"].as_bool().unwrap_or_default(),
 				comments: format_num(data["num_comments"].as_i64().unwrap_or_default()),
 				gallery,
 				awards,
@@ -418,6 +550,28 @@ impl Post {
 
 		Ok((posts, res["data"]["after"].as_str().unwrap_or_default().to_string()))
 	}
+
+	/// Link that downloads the post's media through the proxy instead of
+	/// showing it, saved under a name made from the title and ID. Empty if the
+	/// media isn't proxied.
+	pub fn download_url(&self) -> String {
+		if !self.media.url.starts_with('/') {
+			return String::new();
+		}
+
+		let mut name = String::new();
+		for word in self.title.split(|c: char| !c.is_ascii_alphanumeric()).filter(|w| !w.is_empty()) {
+			if name.len() + word.len() > 60 {
+				break;
+			}
+			name.push_str(&word.to_lowercase());
+			name.push('-');
+		}
+		name.push_str(&self.id);
+
+		let separator = if self.media.url.contains('?') { '&' } else { '?' };
+		format!("{}{}download=1&filename={}", self.media.url, separator, name)
+	}
 }
 
 #[derive(Template)]
@@ -433,15 +587,24 @@ pub struct Comment {
 	pub body: String,
 	pub author: Author,
 	pub score: (String, String),
+	/// Raw up and down vote figures, when Reddit provides more than the score
+	pub votes: String,
 	pub rel_time: String,
 	pub created: String,
 	pub edited: (String, String),
+	pub controversial: bool,
 	pub replies: Vec<Comment>,
 	pub highlighted: bool,
 	pub awards: Awards,
 	pub collapsed: bool,
 	pub is_filtered: bool,
 	pub more_count: i64,
+	/// Whether the comment matches the comment search
+	pub matched: bool,
+	/// ID of the comment matching the comment search after this one
+	pub next_match: String,
+	/// Whether the replies were left out for being nested too deeply
+	pub continue_thread: bool,
 	pub prefs: Preferences,
 }
 
@@ -471,7 +634,7 @@ impl std::ops::Deref for Awards {
 
 impl std::fmt::Display for Awards {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		self.iter().fold(Ok(()), |result, award| result.and_then(|_| writeln!(f, "{}", award)))
+		self.iter().try_for_each(|award| writeln!(f, "{}", award))
 	}
 }
 
@@ -502,10 +665,45 @@ impl Awards {
 #[template(path = "error.html")]
 pub struct ErrorTemplate {
 	pub msg: String,
+	pub status: u16,
+	pub back: String,
+	/// Names of existing subreddits similar to the one that wasn't found.
+	pub suggestions: Vec<String>,
 	pub prefs: Preferences,
 	pub url: String,
 }
 
+impl ErrorTemplate {
+	// Short name of the status code, shown above the message
+	fn reason(&self) -> &'static str {
+		match self.status {
+			400 => "Bad Request",
+			403 => "Forbidden",
+			429 => "Too Many Requests",
+			502 => "Bad Gateway",
+			504 => "Gateway Timeout",
+			_ => "Not Found",
+		}
+	}
+
+	// Explanation of what the status means for the user
+	fn hint(&self) -> &'static str {
+		match self.status {
+			400 => "The request was malformed.",
+			403 => "You don't have access to this content.",
+			429 => "Reddit is rate limiting this instance. Try again in a few minutes.",
+			502 => "Reddit sent back a response Libreddit couldn't understand.",
+			504 => "Reddit took too long to respond.",
+			_ => "The page you're looking for doesn't exist.",
+		}
+	}
+
+	// Whether the error likely originates from Reddit itself
+	fn upstream(&self) -> bool {
+		matches!(self.status, 429 | 502 | 504)
+	}
+}
+
 /// Template for NSFW landing page. The landing page is displayed when a page's
 /// content is wholly NSFW, but a user has not enabled the option to view NSFW
 /// posts.
@@ -533,13 +731,25 @@ pub struct User {
 	pub name: String,
 	pub title: String,
 	pub icon: String,
-	pub karma: i64,
+	pub karma: (String, String),
+	/// Post and comment karma, if Reddit exposes them for this user
+	pub karma_breakdown: Option<((String, String), (String, String))>,
 	pub created: String,
+	pub age: String,
+	pub trophies: Vec<Trophy>,
+	#[allow(dead_code)]
 	pub banner: String,
 	pub description: String,
 	pub nsfw: bool,
 }
 
+// Trophy shown on a user's profile
+pub struct Trophy {
+	pub name: String,
+	pub description: String,
+	pub icon: String,
+}
+
 #[derive(Default)]
 // Subreddit struct containing metadata about community
 pub struct Subreddit {
@@ -547,24 +757,15 @@ pub struct Subreddit {
 	pub title: String,
 	pub description: String,
 	pub info: String,
-	// pub moderators: Vec<String>,
 	pub icon: String,
+	pub banner: String,
+	pub banner_color: String,
 	pub members: (String, String),
 	pub active: (String, String),
 	pub wiki: bool,
 	pub nsfw: bool,
 }
 
-// Parser for query params, used in sorting (eg. /r/rust/?sort=hot)
-#[derive(serde::Deserialize)]
-pub struct Params {
-	pub t: Option<String>,
-	pub q: Option<String>,
-	pub sort: Option<String>,
-	pub after: Option<String>,
-	pub before: Option<String>,
-}
-
 #[derive(Default)]
 pub struct Preferences {
 	pub available_themes: Vec<String>,
@@ -582,7 +783,38 @@ pub struct Preferences {
 	pub post_sort: String,
 	pub subscriptions: Vec<String>,
 	pub filters: Vec<String>,
+	/// Per-subreddit default sorts as (subreddit, sort, timeframe)
+	pub sub_sorts: Vec<(String, String, String)>,
+	/// Named custom feeds, each with the subreddits it combines
+	pub feeds: Vec<(String, Vec<String>)>,
 	pub hide_awards: String,
+	pub show_controversial: String,
+	pub data_saver: String,
+	pub full_res_images: String,
+	pub comment_limit: String,
+	pub show_post_flair: String,
+	pub show_user_flair: String,
+	pub animate_thumbnails: String,
+	pub preview_quality: String,
+	pub show_vote_breakdown: String,
+	pub show_upvote_ratio: String,
+	pub show_vote_buttons: String,
+	pub show_subscriber_counts: String,
+	pub render_math: String,
+	pub inline_threads: String,
+	pub subreddit_names: String,
+	pub link_subreddit_names: String,
+	pub show_domains: String,
+	pub external_links_new_tab: String,
+	pub hide_seen_posts: String,
+	pub hide_comment_scores: String,
+	pub strip_tracking_params: String,
+	pub score_format: String,
+	pub show_author: String,
+	pub ui_density: String,
+	pub collapse_long_quotes: String,
+	pub show_reading_time: String,
+	pub lang: String,
 }
 
 #[derive(RustEmbed)]
@@ -616,9 +848,269 @@ impl Preferences {
 			post_sort: setting(req, "post_sort"),
 			subscriptions: setting(req, "subscriptions").split('+').map(String::from).filter(|s| !s.is_empty()).collect(),
 			filters: setting(req, "filters").split('+').map(String::from).filter(|s| !s.is_empty()).collect(),
+			sub_sorts: parse_sub_sorts(&setting(req, "sub_sorts")),
+			feeds: parse_feeds(&setting(req, "feeds")),
 			hide_awards: setting(req, "hide_awards"),
+			show_controversial: setting(req, "show_controversial"),
+			data_saver: setting(req, "data_saver"),
+			full_res_images: setting(req, "full_res_images"),
+			comment_limit: setting(req, "comment_limit"),
+			show_post_flair: setting(req, "show_post_flair"),
+			show_user_flair: setting(req, "show_user_flair"),
+			animate_thumbnails: setting(req, "animate_thumbnails"),
+			preview_quality: setting(req, "preview_quality"),
+			show_vote_breakdown: setting(req, "show_vote_breakdown"),
+			show_upvote_ratio: setting(req, "show_upvote_ratio"),
+			show_vote_buttons: setting(req, "show_vote_buttons"),
+			show_subscriber_counts: setting(req, "show_subscriber_counts"),
+			render_math: setting(req, "render_math"),
+			inline_threads: setting(req, "inline_threads"),
+			subreddit_names: setting(req, "subreddit_names"),
+			link_subreddit_names: setting(req, "link_subreddit_names"),
+			show_domains: setting(req, "show_domains"),
+			external_links_new_tab: setting(req, "external_links_new_tab"),
+			hide_seen_posts: setting(req, "hide_seen_posts"),
+			hide_comment_scores: setting(req, "hide_comment_scores"),
+			strip_tracking_params: setting(req, "strip_tracking_params"),
+			score_format: setting(req, "score_format"),
+			show_author: setting(req, "show_author"),
+			ui_density: setting(req, "ui_density"),
+			collapse_long_quotes: setting(req, "collapse_long_quotes"),
+			show_reading_time: setting(req, "show_reading_time"),
+			lang: setting(req, "lang"),
+		}
+	}
+
+	/// Whether videos should autoplay. Data saver mode always disables it.
+	pub fn autoplay(&self) -> bool {
+		self.autoplay_videos == "on" && self.data_saver != "on"
+	}
+
+	/// Whether GIFs animate in listings. Data saver mode always disables it.
+	pub fn animate_gifs(&self) -> bool {
+		self.animate_thumbnails == "on" && self.data_saver != "on"
+	}
+
+	/// Whether image posts are shown at full resolution in every layout. Data
+	/// saver mode always disables it.
+	pub fn full_res_images(&self) -> bool {
+		self.full_res_images == "on" && self.data_saver != "on"
+	}
+
+	/// The per-subreddit default sorts in the format they're entered in
+	/// settings, joined by `separator`.
+	pub fn sub_sorts_string(&self, separator: &str) -> String {
+		format_sub_sorts(&self.sub_sorts).replace('+', separator)
+	}
+
+	/// The custom feeds in the format they're entered in settings, joined
+	/// by `separator`.
+	pub fn feeds_string(&self, separator: &str) -> String {
+		format_feeds(&self.feeds).replace('+', separator)
+	}
+
+	/// A score as the `score_format` setting asks for it: abbreviated, like
+	/// 12.3k, or exact. Scores Reddit hides stay hidden either way.
+	pub fn score<'a>(&self, score: &'a (String, String)) -> &'a str {
+		if self.score_format == "full" && score.1.parse::<i64>().is_ok() {
+			&score.1
+		} else {
+			&score.0
+		}
+	}
+
+	/// The URL a link post points to, without tracking parameters unless the
+	/// `strip_tracking_params` setting is off.
+	pub fn outbound_url(&self, url: &str) -> String {
+		if self.strip_tracking_params == "off" {
+			url.to_string()
+		} else {
+			strip_tracking_params(url)
+		}
+	}
+
+	/// `html` with its links off Libreddit opening in a new tab, if the
+	/// `external_links_new_tab` setting asks for it.
+	pub fn external_links(&self, html: &str) -> String {
+		if self.external_links_new_tab == "on" {
+			REGEX_EXTERNAL_LINK
+				.replace_all(html, r#"<a target="_blank" rel="noopener nofollow" href="$1""#)
+				.into_owned()
+		} else {
+			html.to_string()
+		}
+	}
+
+	/// The Libreddit path of a post's community, with its name written the
+	/// way the `subreddit_names` setting asks for.
+	pub fn community_label(&self, community: &str) -> (String, String) {
+		let path = community_path(community);
+		let label = match self.subreddit_names.as_str() {
+			"full" => format!("reddit.com/{}", path),
+			"bare" => community.strip_prefix("u_").unwrap_or(community).to_string(),
+			_ => path.clone(),
+		};
+		(format!("/{}", path), label)
+	}
+}
+
+/// Reading speed assumed for reading time estimates, in words a minute.
+const READING_WORDS_PER_MINUTE: usize = 200;
+
+/// Shortest text given a reading time, in words.
+const MIN_READING_TIME_WORDS: usize = 400;
+
+/// Estimated minutes it takes to read the text in `html`, rounded up. Short
+/// texts that are read at a glance have none.
+pub fn reading_time(html: &str) -> Option<usize> {
+	let words = REGEX_HTML_TAG.replace_all(html, " ").split_whitespace().count();
+	(words >= MIN_READING_TIME_WORDS).then(|| words.div_ceil(READING_WORDS_PER_MINUTE))
+}
+
+/// Reddit's name for `community`, like r/rust, or u/spez for posts made to a
+/// user's profile, which Reddit files under a `u_` subreddit.
+pub fn community_path(community: &str) -> String {
+	match community.strip_prefix("u_") {
+		Some(user) => format!("u/{}", user),
+		None => format!("r/{}", community),
+	}
+}
+
+/// Query parameters that only exist to track who followed a link. `utm_`
+/// parameters are matched by prefix.
+const TRACKING_PARAMS: [&str; 16] = [
+	"fbclid",
+	"gclid",
+	"gclsrc",
+	"dclid",
+	"msclkid",
+	"twclid",
+	"ttclid",
+	"yclid",
+	"igshid",
+	"mc_cid",
+	"mc_eid",
+	"_hsenc",
+	"_hsmi",
+	"mkt_tok",
+	"oly_enc_id",
+	"oly_anon_id",
+];
+
+/// Removes tracking parameters from the query of `url`, leaving the other
+/// parameters exactly as they were written.
+pub fn strip_tracking_params(url: &str) -> String {
+	let (url, fragment) = url.split_once('#').map_or((url, None), |(url, fragment)| (url, Some(fragment)));
+	let Some((base, query)) = url.split_once('?') else {
+		return fragment.map_or_else(|| url.to_string(), |fragment| format!("{}#{}", url, fragment));
+	};
+
+	let kept: Vec<&str> = query
+		.split('&')
+		.filter(|pair| {
+			let key = pair.split('=').next().unwrap_or_default().to_ascii_lowercase();
+			!pair.is_empty() && !key.starts_with("utm_") && !TRACKING_PARAMS.contains(&key.as_str())
+		})
+		.collect();
+
+	let mut stripped = base.to_string();
+	if !kept.is_empty() {
+		stripped.push('?');
+		stripped.push_str(&kept.join("&"));
+	}
+	if let Some(fragment) = fragment {
+		stripped.push('#');
+		stripped.push_str(fragment);
+	}
+	stripped
+}
+
+/// Parses per-subreddit default sorts written as `sub=sort` or
+/// `sub=sort/timeframe`, separated by `+`, commas or whitespace. Invalid
+/// entries are skipped.
+pub fn parse_sub_sorts(value: &str) -> Vec<(String, String, String)> {
+	let mut sorts: Vec<(String, String, String)> = Vec::new();
+
+	for entry in value.split(|c: char| c == '+' || c == ',' || c.is_whitespace()) {
+		let Some((sub, sort)) = entry.split_once('=') else {
+			continue;
+		};
+		let sub = sub.trim_start_matches("r/").to_lowercase();
+		let (sort, timeframe) = sort.split_once('/').unwrap_or((sort, ""));
+
+		let valid_sub = !sub.is_empty() && sub.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+		let valid_sort = ["hot", "new", "top", "rising", "controversial"].contains(&sort);
+		let valid_timeframe = timeframe.is_empty() || (matches!(sort, "top" | "controversial") && ["hour", "day", "week", "month", "year", "all"].contains(&timeframe));
+
+		if valid_sub && valid_sort && valid_timeframe && !sorts.iter().any(|(existing, _, _)| *existing == sub) {
+			sorts.push((sub, sort.to_string(), timeframe.to_string()));
+		}
+	}
+
+	sorts
+}
+
+/// Formats per-subreddit default sorts for storage in the `sub_sorts` cookie.
+pub fn format_sub_sorts(sorts: &[(String, String, String)]) -> String {
+	sorts
+		.iter()
+		.map(|(sub, sort, timeframe)| {
+			if timeframe.is_empty() {
+				format!("{}={}", sub, sort)
+			} else {
+				format!("{}={}/{}", sub, sort, timeframe)
+			}
+		})
+		.collect::<Vec<String>>()
+		.join("+")
+}
+
+/// Parses named custom feeds written as `name=sub1,sub2`, separated by `+`,
+/// semicolons or new lines. Names are case-insensitive, and a name given
+/// twice gets the subreddits of both. Invalid names and subreddits are
+/// skipped.
+pub fn parse_feeds(value: &str) -> Vec<(String, Vec<String>)> {
+	let mut feeds: Vec<(String, Vec<String>)> = Vec::new();
+
+	for entry in value.split(['+', ';', '\n']) {
+		let Some((name, subs)) = entry.split_once('=') else {
+			continue;
+		};
+		let name = name.trim().to_lowercase();
+		if name.is_empty() || name.len() > 32 || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+			continue;
+		}
+
+		let subs = subs
+			.split(|c: char| c == ',' || c.is_whitespace())
+			.map(|sub| sub.trim_start_matches("r/").to_lowercase())
+			.filter(|sub| !sub.is_empty() && sub.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'));
+
+		let index = feeds.iter().position(|(existing, _)| *existing == name).unwrap_or_else(|| {
+			feeds.push((name, Vec::new()));
+			feeds.len() - 1
+		});
+		for sub in subs {
+			if !feeds[index].1.contains(&sub) {
+				feeds[index].1.push(sub);
+			}
 		}
 	}
+
+	feeds.retain(|(_, subs)| !subs.is_empty());
+	feeds
+}
+
+/// Formats custom feeds for storage in the `feeds` cookie.
+pub fn format_feeds(feeds: &[(String, Vec<String>)]) -> String {
+	feeds.iter().map(|(name, subs)| format!("{}={}", name, subs.join(","))).collect::<Vec<String>>().join("+")
+}
+
+/// Gets the IDs of the posts the user marked as seen, oldest first.
+pub fn seen_posts(req: &Request<Body>) -> Vec<String> {
+	req
+		.cookie("seen")
+		.map_or(Vec::new(), |cookie| cookie.value().split('+').filter(|id| !id.is_empty()).map(String::from).collect())
 }
 
 /// Gets a `HashSet` of filters from the cookie in the given `Request`.
@@ -649,10 +1141,54 @@ pub fn filter_posts(posts: &mut Vec<Post>, filters: &HashSet<String>) -> (u64, b
 	}
 }
 
+// Explains a post's `removed_by_category`, and whether to link to an archived
+// copy of it. Authors' own deletions and legal takedowns aren't linked.
+fn removal_reason(category: &str) -> (&'static str, bool) {
+	match category {
+		"moderator" => ("Removed by moderators", true),
+		"automod_filtered" => ("Removed by AutoModerator", true),
+		"deleted" | "author" => ("Deleted by author", false),
+		"copyright_takedown" => ("Removed for copyright", false),
+		"legal_operations" | "content_takedown" => ("Removed for legal reasons", false),
+		"anti_evil_ops" | "community_ops" | "reddit" => ("Removed by Reddit", true),
+		_ => ("Removed", true),
+	}
+}
+
+/// Icon of the subreddit `sr_detail` describes, which Reddit sends with each
+/// post of a listing when asked, saving a request per subreddit.
+fn community_icon(sr_detail: &Value) -> String {
+	let icon = [&sr_detail["community_icon"], &sr_detail["icon_img"]]
+		.into_iter()
+		.filter_map(Value::as_str)
+		.find(|icon| !icon.is_empty())
+		.unwrap_or_default();
+	format_url(icon)
+}
+
+/// Names the kind of post if it's one Reddit builds with data Libreddit
+/// doesn't render, like prediction tournaments, so it isn't just left blank.
+fn unsupported_kind(data: &Value) -> Option<&'static str> {
+	if data["tournament_data"].is_object() {
+		Some("prediction tournament")
+	} else if data["discussion_type"] == "CHAT" {
+		Some("live chat")
+	} else if data["rpan_video"].is_object() {
+		Some("live stream")
+	} else {
+		match data["post_hint"].as_str().unwrap_or_default() {
+			"" | "self" | "image" | "link" | "hosted:video" | "rich:video" => None,
+			_ => Some("rich"),
+		}
+	}
+}
+
 /// Creates a [`Post`] from a provided JSON.
 pub async fn parse_post(post: &serde_json::Value) -> Post {
 	// Grab UTC time as unix timestamp
 	let (rel_time, created) = time(post["data"]["created_utc"].as_f64().unwrap_or_default());
+	// Reddit sends `false` for posts that were never edited
+	let edited = post["data"]["edited"].as_f64().map_or((String::new(), String::new()), time);
 	// Parse post score and upvote ratio
 	let score = post["data"]["score"].as_i64().unwrap_or_default();
 	let ratio: f64 = post["data"]["upvote_ratio"].as_f64().unwrap_or(1.0) * 100.0;
@@ -666,14 +1202,28 @@ pub async fn parse_post(post: &serde_json::Value) -> Post {
 
 	let poll = Poll::parse(&post["data"]["poll_data"]);
 
-	let body = if val(post, "removed_by_category") == "moderator" {
-		format!(
-			"<div class=\"md\"><p>[removed] — <a href=\"https://{}{}\">view removed post</a></p></div>",
-			get_setting("LIBREDDIT_PUSHSHIFT_FRONTEND").unwrap_or(String::from(crate::config::DEFAULT_PUSHSHIFT_FRONTEND)),
-			permalink
-		)
+	let removed_by = val(post, "removed_by_category");
+	let body = if removed_by.is_empty() {
+		let body = rewrite_urls(&inline_media(&val(post, "selftext_html"), &post["data"]["media_metadata"]));
+		match unsupported_kind(&post["data"]) {
+			Some(kind) => format!(
+				"{}<div class=\"post_notification\"><p>This is a {} post, which Libreddit can't fully show yet. The rest of it is on Reddit.</p></div>",
+				body, kind
+			),
+			None => body,
+		}
 	} else {
-		rewrite_urls(&val(post, "selftext_html"))
+		let (reason, archived) = removal_reason(&removed_by);
+		if archived {
+			format!(
+				"<div class=\"md\"><p>[{}] — <a href=\"https://{}{}\">view removed post</a></p></div>",
+				reason,
+				get_setting("LIBREDDIT_PUSHSHIFT_FRONTEND").unwrap_or(String::from(crate::config::DEFAULT_PUSHSHIFT_FRONTEND)),
+				permalink
+			)
+		} else {
+			format!("<div class=\"md\"><p>[{}]</p></div>", reason)
+		}
 	};
 
 	// Build a post using data parsed from Reddit post API
@@ -705,6 +1255,8 @@ pub async fn parse_post(post: &serde_json::Value) -> Post {
 		thumbnail: Media {
 			url: format_url(val(post, "thumbnail").as_str()),
 			alt_url: String::new(),
+			low_res_url: String::new(),
+			resolutions: Vec::new(),
 			width: post["data"]["thumbnail_width"].as_i64().unwrap_or_default(),
 			height: post["data"]["thumbnail_height"].as_i64().unwrap_or_default(),
 			poster: String::new(),
@@ -730,7 +1282,13 @@ pub async fn parse_post(post: &serde_json::Value) -> Post {
 		domain: val(post, "domain"),
 		rel_time,
 		created,
+		edited,
 		num_duplicates: post["data"]["num_duplicates"].as_u64().unwrap_or(0),
+		num_crossposts: post["data"]["num_crossposts"].as_u64().unwrap_or(0),
+		crosspost: Crosspost::parse(&post["data"]),
+		subscribers: post["data"]["subreddit_subscribers"].as_i64().map(format_num),
+		community_icon: community_icon(&post["data"]["sr_detail"]),
+		contest_mode: post["data"]["contest_mode"].as_bool().unwrap_or_default(),
 		comments: format_num(post["data"]["num_comments"].as_i64().unwrap_or_default()),
 		gallery,
 		awards,
@@ -755,8 +1313,112 @@ pub fn param(path: &str, value: &str) -> Option<String> {
 	)
 }
 
+/// Wraps the case-insensitive matches of `query` in the text of `html` in
+/// `<mark>` tags, leaving tags and their attributes alone.
+pub fn highlight_matches(html: &str, query: &str) -> String {
+	let mut result = String::with_capacity(html.len());
+	let mut copied = 0;
+	for (start, end) in text_matches(html, query) {
+		result.push_str(&html[copied..start]);
+		result.push_str("<mark>");
+		result.push_str(&html[start..end]);
+		result.push_str("</mark>");
+		copied = end;
+	}
+	result.push_str(&html[copied..]);
+
+	result
+}
+
+/// Whether the text of `html` contains `query`, ignoring case. Agrees with
+/// what `highlight_matches` marks.
+pub fn html_contains(html: &str, query: &str) -> bool {
+	!text_matches(html, query).is_empty()
+}
+
+/// Byte ranges of the case-insensitive matches of `query` in the text of
+/// `html`. Matches don't run across tags, and entities match the character
+/// they stand for, so a match never splits one.
+fn text_matches(html: &str, query: &str) -> Vec<(usize, usize)> {
+	// Adds the matches among `chars` to `matches`, where each character is
+	// given with the byte range it came from. Unknown entities match nothing.
+	fn find(chars: &[(Option<char>, usize, usize)], query: &[char], matches: &mut Vec<(usize, usize)>) {
+		let mut i = 0;
+		while i + query.len() <= chars.len() {
+			if chars[i..i + query.len()].iter().zip(query).all(|((c, _, _), q)| *c == Some(*q)) {
+				matches.push((chars[i].1, chars[i + query.len() - 1].2));
+				i += query.len();
+			} else {
+				i += 1;
+			}
+		}
+	}
+
+	let query: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+	let mut matches = Vec::new();
+	if query.is_empty() {
+		return matches;
+	}
+
+	let mut chars = Vec::new();
+	let mut pos = 0;
+	while let Some(rest) = html.get(pos..).filter(|rest| !rest.is_empty()) {
+		if rest.starts_with('<') {
+			find(&chars, &query, &mut matches);
+			chars.clear();
+			pos += rest.find('>').map_or(rest.len(), |end| end + 1);
+			continue;
+		}
+
+		let entity = rest
+			.strip_prefix('&')
+			.and_then(|entity| entity.get(..entity.find(';')?))
+			.filter(|name| !name.is_empty() && name.len() <= 10 && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '#'));
+		let (c, len) = match entity {
+			Some(name) => (decode_entity(name), name.len() + 2),
+			None => {
+				let c = rest.chars().next().unwrap_or_default();
+				(Some(c), c.len_utf8())
+			}
+		};
+		match c {
+			Some(c) => chars.extend(c.to_lowercase().map(|lower| (Some(lower), pos, pos + len))),
+			None => chars.push((None, pos, pos + len)),
+		}
+		pos += len;
+	}
+	find(&chars, &query, &mut matches);
+
+	matches
+}
+
+/// The character an HTML entity like `amp` or `#39` stands for, if known.
+fn decode_entity(name: &str) -> Option<char> {
+	match name {
+		"amp" => Some('&'),
+		"lt" => Some('<'),
+		"gt" => Some('>'),
+		"quot" => Some('"'),
+		"apos" => Some('\''),
+		"nbsp" => Some('\u{a0}'),
+		_ => {
+			let number = name.strip_prefix('#')?;
+			let code = match number.strip_prefix(['x', 'X']) {
+				Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+				None => number.parse().ok()?,
+			};
+			char::from_u32(code)
+		}
+	}
+}
+
 // Retrieve the value of a setting by name
 pub fn setting(req: &Request<Body>, name: &str) -> String {
+	// Subscription cookies are ignored altogether when subscriptions are off
+	if name == "subscriptions" && !subscriptions_enabled() {
+		return String::new();
+	}
+
 	// Parse a cookie value from request
 	req
 		.cookie(name)
@@ -785,6 +1447,10 @@ pub async fn catch_random(sub: &str, additional: &str) -> Result<Response<Body>,
 	}
 }
 
+// Markup to strip from bodies before counting their words
+static REGEX_HTML_TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+// Links rewrite_urls left absolute, which all lead off Libreddit
+static REGEX_EXTERNAL_LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<a href="(https?://[^"]*)""#).unwrap());
 static REGEX_URL_WWW: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://www\.reddit\.com/(.*)").unwrap());
 static REGEX_URL_OLD: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://old\.reddit\.com/(.*)").unwrap());
 static REGEX_URL_NP: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://np\.reddit\.com/(.*)").unwrap());
@@ -800,6 +1466,67 @@ static REGEX_URL_EXTERNAL_PREVIEW: Lazy<Regex> = Lazy::new(|| Regex::new(r"https
 static REGEX_URL_STYLES: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://styles\.redditmedia\.com/(.*)").unwrap());
 static REGEX_URL_STATIC_MEDIA: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://www\.redditstatic\.com/(.*)").unwrap());
 
+/// Prefix of the routes proxying Reddit's media (e.g. `/media` for
+/// `/media/img/...`). Empty unless set through `--proxy-path`.
+pub static PROXY_PATH: OnceCell<String> = OnceCell::new();
+
+pub fn proxy_path() -> &'static str {
+	PROXY_PATH.get().map_or("", String::as_str)
+}
+
+/// Whether media is loaded by browsers straight from Reddit instead of
+/// through Libreddit, set with `--hotlink-media`. Saves the instance's
+/// bandwidth at the cost of users' privacy.
+pub static HOTLINK_MEDIA: AtomicBool = AtomicBool::new(false);
+
+pub fn hotlink_media() -> bool {
+	HOTLINK_MEDIA.load(Ordering::Relaxed)
+}
+
+/// The hosts browsers load images and videos from when media is hotlinked
+pub const HOTLINKED_IMAGE_HOSTS: &[&str] = &[
+	"i.redd.it",
+	"preview.redd.it",
+	"external-preview.redd.it",
+	"a.thumbs.redditmedia.com",
+	"b.thumbs.redditmedia.com",
+	"emoji.redditmedia.com",
+	"styles.redditmedia.com",
+	"www.redditstatic.com",
+	"i.imgur.com",
+];
+pub const HOTLINKED_VIDEO_HOSTS: &[&str] = &["v.redd.it"];
+
+/// Path Libreddit is hosted under behind a reverse proxy (e.g. `/reddit` for
+/// `example.com/reddit/`). Empty unless set through `--base-path`.
+pub static BASE_PATH: OnceCell<String> = OnceCell::new();
+
+pub fn base_path() -> &'static str {
+	BASE_PATH.get().map_or("", String::as_str)
+}
+
+/// Whether pages should point search engines at the original post, subreddit
+/// or profile on reddit.com. Off unless turned on with `--canonical-links`.
+pub static CANONICAL_LINKS: AtomicBool = AtomicBool::new(false);
+
+pub fn canonical_links() -> bool {
+	CANONICAL_LINKS.load(Ordering::Relaxed)
+}
+
+static REGEX_ROOT_URL: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(href="|src="|action="|poster="|url\(')/([^/])"#).unwrap());
+
+/// Prefixes the root-relative URLs in rendered `html` with the base path.
+pub fn rebase_html(html: String) -> String {
+	let base = base_path();
+	if base.is_empty() {
+		return html;
+	}
+
+	REGEX_ROOT_URL
+		.replace_all(&html, |caps: &Captures| format!("{}{}/{}", &caps[1], base, &caps[2]))
+		.into_owned()
+}
+
 // Direct urls to proxy if proxy is enabled
 pub fn format_url(url: &str) -> String {
 	if url.is_empty() || url == "self" || url == "default" || url == "nsfw" || url == "spoiler" {
@@ -816,6 +1543,9 @@ pub fn format_url(url: &str) -> String {
 				})
 			};
 
+			// Media routes live under the proxy prefix
+			let proxied = |route: &str| [proxy_path(), route].concat();
+
 			macro_rules! chain {
 				() => {
 					{
@@ -837,34 +1567,106 @@ pub fn format_url(url: &str) -> String {
 				};
 			}
 
+			if hotlink_media() && (HOTLINKED_IMAGE_HOSTS.contains(&domain) || HOTLINKED_VIDEO_HOSTS.contains(&domain)) {
+				return url.to_string();
+			}
+
 			match domain {
 				"www.reddit.com" => capture(&REGEX_URL_WWW, "/", 1),
 				"old.reddit.com" => capture(&REGEX_URL_OLD, "/", 1),
 				"np.reddit.com" => capture(&REGEX_URL_NP, "/", 1),
 				"reddit.com" => capture(&REGEX_URL_PLAIN, "/", 1),
-				"v.redd.it" => chain!(capture(&REGEX_URL_VIDEOS, "/vid/", 2), capture(&REGEX_URL_VIDEOS_HLS, "/hls/", 2)),
-				"i.redd.it" => capture(&REGEX_URL_IMAGES, "/img/", 1),
-				"a.thumbs.redditmedia.com" => capture(&REGEX_URL_THUMBS_A, "/thumb/a/", 1),
-				"b.thumbs.redditmedia.com" => capture(&REGEX_URL_THUMBS_B, "/thumb/b/", 1),
-				"emoji.redditmedia.com" => capture(&REGEX_URL_EMOJI, "/emoji/", 2),
-				"preview.redd.it" => capture(&REGEX_URL_PREVIEW, "/preview/pre/", 1),
-				"external-preview.redd.it" => capture(&REGEX_URL_EXTERNAL_PREVIEW, "/preview/external-pre/", 1),
-				"styles.redditmedia.com" => capture(&REGEX_URL_STYLES, "/style/", 1),
-				"www.redditstatic.com" => capture(&REGEX_URL_STATIC_MEDIA, "/static/", 1),
+				"v.redd.it" => chain!(capture(&REGEX_URL_VIDEOS, &proxied("/vid/"), 2), capture(&REGEX_URL_VIDEOS_HLS, &proxied("/hls/"), 2)),
+				"i.redd.it" => capture(&REGEX_URL_IMAGES, &proxied("/img/"), 1),
+				"a.thumbs.redditmedia.com" => capture(&REGEX_URL_THUMBS_A, &proxied("/thumb/a/"), 1),
+				"b.thumbs.redditmedia.com" => capture(&REGEX_URL_THUMBS_B, &proxied("/thumb/b/"), 1),
+				"emoji.redditmedia.com" => capture(&REGEX_URL_EMOJI, &proxied("/emoji/"), 2),
+				"preview.redd.it" => capture(&REGEX_URL_PREVIEW, &proxied("/preview/pre/"), 1),
+				"external-preview.redd.it" => capture(&REGEX_URL_EXTERNAL_PREVIEW, &proxied("/preview/external-pre/"), 1),
+				"styles.redditmedia.com" => capture(&REGEX_URL_STYLES, &proxied("/style/"), 1),
+				"www.redditstatic.com" => capture(&REGEX_URL_STATIC_MEDIA, &proxied("/static/"), 1),
 				_ => url.to_string(),
 			}
 		})
 	}
 }
 
-static REDDIT_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"href="(https|http|)://(www\.|old\.|np\.|amp\.|)(reddit\.com|redd\.it)/"#).unwrap());
+static REDDIT_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"href="((?:https|http|)://(?:www\.|old\.|np\.|amp\.|)(?:reddit\.com|redd\.it)/[^"]*)""#).unwrap());
 static REDDIT_PREVIEW_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"https://(external-preview|preview)\.redd\.it(.*)[^?]").unwrap());
+static REDDIT_MEDIA_LINK_REGEX: Lazy<Regex> =
+	Lazy::new(|| Regex::new(r#"<a href="(https://(?:preview|i)\.redd\.it/([A-Za-z0-9]+)\.[A-Za-z0-9]+[^"]*)"[^>]*>(.*?)</a>"#).unwrap());
+
+/// Replaces links to images that were uploaded into a post's body (listed in
+/// its `media_metadata`) with the images themselves. The link text is used as
+/// the caption if it isn't just the URL.
+pub fn inline_media(body: &str, media_metadata: &Value) -> String {
+	if !media_metadata.is_object() {
+		return body.to_string();
+	}
+
+	REDDIT_MEDIA_LINK_REGEX
+		.replace_all(body, |caps: &Captures| {
+			let media = &media_metadata[&caps[2]];
+			let source = media["s"]["gif"].as_str().or_else(|| media["s"]["u"].as_str()).unwrap_or_default();
+
+			if media["status"] != "valid" || source.is_empty() {
+				return caps[0].to_string();
+			}
+
+			let url = format_url(source);
+			let caption = if caps[3] == caps[1] { "" } else { &caps[3] };
+			format!(
+				"<figure><a href=\"{url}\"><img loading=\"lazy\" class=\"inline_media\" src=\"{url}\" alt=\"{}\"></a>{}</figure>",
+				if caption.is_empty() { "Image".to_string() } else { caption.replace('"', "&quot;") },
+				if caption.is_empty() {
+					String::new()
+				} else {
+					format!("<figcaption>{}</figcaption>", caption)
+				}
+			)
+		})
+		.to_string()
+}
+
+/// Maps a Reddit URL to the path of the same page on Libreddit. Handles
+/// reddit.com and its subdomains, `redd.it` short links and Reddit's media
+/// hosts, and returns `None` for anything else.
+pub fn reddit_path(url: &str) -> Option<String> {
+	let url = url.trim();
+	let rest = ["https://", "http://", "://", "//"].iter().find_map(|scheme| url.strip_prefix(scheme)).unwrap_or(url);
+	let (host, path) = match rest.find(['/', '?', '#']) {
+		Some(i) => (&rest[..i], &rest[i..]),
+		None => (rest, ""),
+	};
+	let path = if path.starts_with('/') { path.to_string() } else { format!("/{}", path) };
+
+	match host.to_lowercase().as_str() {
+		"reddit.com" | "www.reddit.com" | "old.reddit.com" | "new.reddit.com" | "np.reddit.com" | "amp.reddit.com" | "m.reddit.com" => Some(match path.strip_prefix("/u/") {
+			Some(user) => format!("/user/{}", user),
+			None => path,
+		}),
+		// Short links are just the post ID
+		"redd.it" => {
+			let id = path[1..].split(['/', '?', '#']).next().unwrap_or_default();
+			Some(if id.is_empty() { "/".to_string() } else { format!("/comments/{}", id) })
+		}
+		"i.redd.it"
+		| "v.redd.it"
+		| "preview.redd.it"
+		| "external-preview.redd.it"
+		| "a.thumbs.redditmedia.com"
+		| "b.thumbs.redditmedia.com"
+		| "emoji.redditmedia.com"
+		| "styles.redditmedia.com" => Some(format_url(&format!("https://{}{}", host.to_lowercase(), path))).filter(|proxied| proxied.starts_with('/')),
+		_ => None,
+	}
+}
 
 // Rewrite Reddit links to Libreddit in body of text
 pub fn rewrite_urls(input_text: &str) -> String {
 	let text1 =
 		// Rewrite Reddit links to Libreddit
-		REDDIT_REGEX.replace_all(input_text, r#"href="/"#)
+		REDDIT_REGEX.replace_all(input_text, |caps: &Captures| format!(r#"href="{}""#, reddit_path(&caps[1]).unwrap_or_else(|| caps[1].to_string())))
 			.to_string()
 			// Remove (html-encoded) "\" from URLs.
 			.replace("%5C", "")
@@ -884,7 +1686,8 @@ pub fn rewrite_urls(input_text: &str) -> String {
 // Append `m` and `k` for millions and thousands respectively, and
 // round to the nearest tenth.
 pub fn format_num(num: i64) -> (String, String) {
-	let truncated = if num >= 1_000_000 || num <= -1_000_000 {
+	// Numbers just short of a million would round up to "1000.0k"
+	let truncated = if num >= 999_950 || num <= -999_950 {
 		format!("{:.1}m", num as f64 / 1_000_000.0)
 	} else if num >= 1000 || num <= -1000 {
 		format!("{:.1}k", num as f64 / 1_000.0)
@@ -940,37 +1743,277 @@ pub fn val(j: &Value, k: &str) -> String {
 // NETWORKING
 //
 
+/// Coalesces concurrent calls that share a key into one: the first caller
+/// runs the future, and everyone who arrives while it's in flight waits for
+/// and shares its result. Keeps a cold cache from sending a burst of
+/// identical requests to Reddit.
+pub struct SingleFlight<T> {
+	in_flight: Mutex<HashMap<String, watch::Receiver<Option<T>>>>,
+}
+
+/// Removes a call from the in-flight map once its leader finishes or is
+/// dropped (e.g. when the client disconnects).
+struct FlightGuard<'a, T> {
+	flights: &'a SingleFlight<T>,
+	key: String,
+}
+
+impl<T> Drop for FlightGuard<'_, T> {
+	fn drop(&mut self) {
+		if let Ok(mut in_flight) = self.flights.in_flight.lock() {
+			in_flight.remove(&self.key);
+		}
+	}
+}
+
+impl<T: Clone> SingleFlight<T> {
+	pub fn new() -> Self {
+		Self {
+			in_flight: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Runs `fetch`, unless a call for `key` is already running, in which case
+	/// its result is returned instead.
+	pub async fn run<F: Future<Output = T>>(&self, key: &str, fetch: impl FnOnce() -> F) -> T {
+		let leader = {
+			let mut in_flight = self.in_flight.lock().unwrap_or_else(PoisonError::into_inner);
+			match in_flight.get(key) {
+				Some(receiver) => Err(receiver.clone()),
+				None => {
+					let (sender, receiver) = watch::channel(None);
+					in_flight.insert(key.to_string(), receiver);
+					Ok(sender)
+				}
+			}
+		};
+
+		match leader {
+			Ok(sender) => {
+				let guard = FlightGuard {
+					flights: self,
+					key: key.to_string(),
+				};
+				let result = fetch().await;
+				drop(guard);
+				let _ = sender.send(Some(result.clone()));
+				result
+			}
+			Err(mut receiver) => loop {
+				if let Some(result) = receiver.borrow_and_update().clone() {
+					return result;
+				}
+				// The leader was dropped before finishing, so fetch it ourselves
+				if receiver.changed().await.is_err() {
+					return fetch().await;
+				}
+			},
+		}
+	}
+}
+
+/// Content type of every HTML page, so browsers never have to guess the
+/// encoding of non-English text
+pub const HTML_CONTENT_TYPE: &str = "text/html; charset=utf-8";
+
 pub fn template(t: impl Template) -> Result<Response<Body>, String> {
 	Ok(
 		Response::builder()
 			.status(200)
-			.header("content-type", "text/html")
-			.body(t.render().unwrap_or_default().into())
+			.header("content-type", HTML_CONTENT_TYPE)
+			.body(rebase_html(t.render().unwrap_or_default()).into())
 			.unwrap_or_default(),
 	)
 }
 
+/// Whether `?debug=json` may be used to see the JSON a page was rendered
+/// from, set with `--enable-debug`. Off by default since it exposes
+/// Reddit's responses as-is.
+pub static ENABLE_DEBUG: AtomicBool = AtomicBool::new(false);
+
+/// Whether the request asks for the raw JSON behind the page, and the
+/// instance allows it
+pub fn debug_json(req: &Request<Body>) -> bool {
+	ENABLE_DEBUG.load(Ordering::Relaxed) && param(&format!("?{}", req.uri().query().unwrap_or_default()), "debug").as_deref() == Some("json")
+}
+
+/// Responds with Reddit's JSON for `path`, unparsed, for debugging rendering
+pub async fn debug_response(path: &str, quarantined: bool) -> Result<Response<Body>, String> {
+	let res = json(path.to_string(), quarantined).await?;
+
+	Ok(
+		Response::builder()
+			.status(200)
+			.header("content-type", "application/json")
+			.header(header::CACHE_CONTROL, "no-store")
+			.header("X-Robots-Tag", "noindex")
+			.body(serde_json::to_string_pretty(&res).unwrap_or_default().into())
+			.unwrap_or_default(),
+	)
+}
+
+/// How long browsers may reuse the kinds of pages Libreddit renders. Pages
+/// depend on the preferences in the visitor's cookies, so they're only
+/// cached privately and vary with the cookies: changing a setting or
+/// subscribing shows up right away.
+#[derive(Clone, Copy)]
+pub enum CachePolicy {
+	/// Subreddit, user and search listings, which change by the minute
+	Listing,
+	/// Posts with their comments
+	Post,
+	/// Wiki pages, sidebars and moderator lists, which rarely change
+	About,
+	/// Pages that must be fetched again every time, such as settings
+	NoCache,
+}
+
+impl CachePolicy {
+	fn cache_control(self) -> &'static str {
+		match self {
+			Self::Listing => "private, max-age=60",
+			Self::Post => "private, max-age=300",
+			Self::About => "private, max-age=3600",
+			Self::NoCache => "private, no-cache",
+		}
+	}
+}
+
+/// Adds the caching headers for `policy` to a successful response.
+pub fn cache(res: Result<Response<Body>, String>, policy: CachePolicy) -> Result<Response<Body>, String> {
+	res.map(|mut res| {
+		if res.status() == 200 {
+			res.headers_mut().insert(header::CACHE_CONTROL, HeaderValue::from_static(policy.cache_control()));
+			res.headers_mut().append(header::VARY, HeaderValue::from_static("Cookie"));
+		}
+		res
+	})
+}
+
 pub fn redirect(path: String) -> Response<Body> {
 	Response::builder()
 		.status(302)
-		.header("content-type", "text/html")
+		.header("content-type", HTML_CONTENT_TYPE)
 		.header("Location", &path)
 		.body(format!("Redirecting to <a href=\"{0}\">{0}</a>...", path).into())
 		.unwrap_or_default()
 }
 
-/// Renders a generic error landing page.
-pub async fn error(req: Request<Body>, msg: impl ToString) -> Result<Response<Body>, String> {
+/// Renders a generic error landing page, with the status of a failed request
+/// to Reddit or a 404 for messages of Libreddit's own.
+pub async fn error(req: Request<Body>, e: impl Into<FetchError>) -> Result<Response<Body>, String> {
+	let e = e.into();
+	error_with_status(req, e.status, e.msg).await
+}
+
+/// Renders an error page with the HTTP status code `status`.
+pub async fn error_with_status(req: Request<Body>, status: u16, msg: impl ToString) -> Result<Response<Body>, String> {
+	error_page(req, status, msg.to_string(), Vec::new())
+}
+
+/// Renders a 404 error page listing `suggestions` as subreddits the user may
+/// have meant instead.
+pub async fn error_with_suggestions(req: Request<Body>, msg: impl ToString, suggestions: Vec<String>) -> Result<Response<Body>, String> {
+	error_page(req, 404, msg.to_string(), suggestions)
+}
+
+fn error_page(req: Request<Body>, status: u16, msg: String, suggestions: Vec<String>) -> Result<Response<Body>, String> {
+	// Until Reddit has been reached once, upstream failures are most likely
+	// down to the instance's network rather than Reddit
+	let starting = status >= 500 && upstream_unconfirmed();
+	let (status, msg) = if starting {
+		(503, "Libreddit is starting up and can't reach Reddit yet. Try again in a moment.".to_string())
+	} else {
+		(status, msg)
+	};
+
 	let url = req.uri().to_string();
-	let body = ErrorTemplate {
-		msg: msg.to_string(),
-		prefs: Preferences::new(&req),
-		url,
+	let body = rebase_html(
+		ErrorTemplate {
+			msg,
+			status,
+			back: parent_path(req.uri().path()),
+			suggestions,
+			prefs: Preferences::new(&req),
+			url,
+		}
+		.render()
+		.unwrap_or_default(),
+	);
+
+	let mut res = Response::builder().status(status).header("content-type", HTML_CONTENT_TYPE);
+	if starting {
+		res = res.header("Retry-After", crate::client::UPSTREAM_CHECK_INTERVAL.as_secs().to_string());
 	}
-	.render()
-	.unwrap_or_default();
+	Ok(res.body(body.into()).unwrap_or_default())
+}
 
-	Ok(Response::builder().status(404).header("content-type", "text/html").body(body.into()).unwrap_or_default())
+/// Consecutive upstream fetches that failed because Reddit itself was down.
+static UPSTREAM_FAILURES: AtomicU32 = AtomicU32::new(0);
+
+/// How many failed fetches in a row it takes before pages warn about an outage.
+const DEGRADED_THRESHOLD: u32 = 3;
+
+/// Languages that can be picked in the settings, by language tag. Reddit
+/// translates some of what it sends, like flairs and relative dates.
+pub const LANGUAGES: [(&str, &str); 9] = [
+	("en", "English"),
+	("de", "Deutsch"),
+	("es", "Español"),
+	("fr", "Français"),
+	("it", "Italiano"),
+	("nl", "Nederlands"),
+	("pl", "Polski"),
+	("pt", "Português"),
+	("sv", "Svenska"),
+];
+
+/// Set through `--disable-subscriptions`, for instances that keep no
+/// subscriptions for their visitors.
+pub static DISABLE_SUBSCRIPTIONS: AtomicBool = AtomicBool::new(false);
+
+pub fn subscriptions_enabled() -> bool {
+	!DISABLE_SUBSCRIPTIONS.load(Ordering::Relaxed)
+}
+
+/// Set by `--check-upstream` until a fetch proves Reddit can be reached.
+pub static UPSTREAM_UNCONFIRMED: AtomicBool = AtomicBool::new(false);
+
+/// Tracks whether Reddit is up based on the result of a fetch. Successes and
+/// errors about the content itself (private, not found, ...) reset the count,
+/// while upstream failures add to it. Rate limiting says nothing either way.
+/// Anything but an upstream failure shows that Reddit is reachable.
+pub fn record_upstream<T>(result: &Result<T, FetchError>) {
+	match result {
+		Ok(_) => UPSTREAM_FAILURES.store(0, Ordering::Relaxed),
+		Err(e) => match e.status {
+			429 => {}
+			status if status >= 500 => {
+				UPSTREAM_FAILURES.fetch_add(1, Ordering::Relaxed);
+				return;
+			}
+			_ => UPSTREAM_FAILURES.store(0, Ordering::Relaxed),
+		},
+	}
+	UPSTREAM_UNCONFIRMED.store(false, Ordering::Relaxed);
+}
+
+/// Whether the startup check is still waiting to reach Reddit.
+pub fn upstream_unconfirmed() -> bool {
+	UPSTREAM_UNCONFIRMED.load(Ordering::Relaxed)
+}
+
+/// Whether enough upstream fetches have failed in a row to show the outage banner.
+pub fn reddit_degraded() -> bool {
+	UPSTREAM_FAILURES.load(Ordering::Relaxed) >= DEGRADED_THRESHOLD
+}
+
+// Path one level up from `path`, used for the "go back" link on error pages
+fn parent_path(path: &str) -> String {
+	match path.trim_end_matches('/').rsplit_once('/') {
+		Some((parent, _)) if !parent.is_empty() => parent.to_string(),
+		_ => "/".to_string(),
+	}
 }
 
 /// Returns true if the config/env variable `LIBREDDIT_SFW_ONLY` carries the
@@ -1016,21 +2059,258 @@ pub async fn nsfw_landing(req: Request<Body>, req_url: String) -> Result<Respons
 		req.param("sub").unwrap_or_default()
 	};
 
-	let body = NSFWLandingTemplate {
-		res,
-		res_type,
-		prefs: Preferences::new(&req),
-		url: req_url,
-	}
-	.render()
-	.unwrap_or_default();
+	let body = rebase_html(
+		NSFWLandingTemplate {
+			res,
+			res_type,
+			prefs: Preferences::new(&req),
+			url: req_url,
+		}
+		.render()
+		.unwrap_or_default(),
+	);
 
-	Ok(Response::builder().status(403).header("content-type", "text/html").body(body.into()).unwrap_or_default())
+	Ok(
+		Response::builder()
+			.status(403)
+			.header("content-type", HTML_CONTENT_TYPE)
+			.body(body.into())
+			.unwrap_or_default(),
+	)
 }
 
 #[cfg(test)]
 mod tests {
-	use super::{format_num, format_url, rewrite_urls};
+	use super::{
+		format_feeds, format_num, format_sub_sorts, format_url, highlight_matches, html_contains, inline_media, parent_path, parse_feeds, parse_sub_sorts, reading_time,
+		record_upstream, reddit_degraded, reddit_path, rewrite_urls, strip_tracking_params, unsupported_kind, Crosspost, FetchError, Media, SingleFlight,
+	};
+	use futures_lite::future::{block_on, yield_now, zip};
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	#[test]
+	fn test_single_flight() {
+		let flights = SingleFlight::new();
+		let fetches = AtomicUsize::new(0);
+		let fetch = || async {
+			fetches.fetch_add(1, Ordering::SeqCst);
+			yield_now().await;
+			"page".to_string()
+		};
+
+		// Concurrent calls for the same key share one fetch
+		let (first, second) = block_on(zip(flights.run("/r/rust", fetch), flights.run("/r/rust", fetch)));
+		assert_eq!((first.as_str(), second.as_str()), ("page", "page"));
+		assert_eq!(fetches.load(Ordering::SeqCst), 1);
+
+		// Other keys, and later calls, fetch again
+		block_on(zip(flights.run("/r/rust", fetch), flights.run("/r/linux", fetch)));
+		assert_eq!(fetches.load(Ordering::SeqCst), 3);
+	}
+
+	#[test]
+	fn test_listing_url() {
+		let media = Media {
+			url: "/img/full.jpg".to_string(),
+			alt_url: String::new(),
+			low_res_url: String::new(),
+			resolutions: vec![
+				(320, "/preview/pre/a.jpg".to_string()),
+				(640, "/preview/pre/b.jpg".to_string()),
+				(1080, "/preview/pre/c.jpg".to_string()),
+			],
+			width: 2000,
+			height: 1000,
+			poster: "/preview/pre/still.jpg".to_string(),
+		};
+		assert_eq!(media.listing_url(false, "low"), "/preview/pre/a.jpg");
+		assert_eq!(media.listing_url(false, "high"), "/preview/pre/c.jpg");
+		assert_eq!(media.listing_url(false, "source"), "/img/full.jpg");
+		assert_eq!(media.listing_url(false, ""), "/img/full.jpg");
+
+		let gif = Media {
+			url: "/img/anim.gif".to_string(),
+			..media
+		};
+		assert_eq!(gif.listing_url(false, "low"), "/preview/pre/still.jpg");
+		assert_eq!(gif.listing_url(true, "low"), "/img/anim.gif");
+	}
+
+	#[test]
+	fn test_parse_feeds() {
+		let feeds = parse_feeds("News = worldnews, r/Politics\nlinux=archlinux debian+news=europe,worldnews;bad name=rust;empty=,;=pics");
+		assert_eq!(
+			feeds,
+			vec![
+				("news".to_string(), vec!["worldnews".to_string(), "politics".to_string(), "europe".to_string()]),
+				("linux".to_string(), vec!["archlinux".to_string(), "debian".to_string()]),
+			]
+		);
+		assert_eq!(format_feeds(&feeds), "news=worldnews,politics,europe+linux=archlinux,debian");
+		assert_eq!(parse_feeds(&format_feeds(&feeds)), feeds);
+		assert!(parse_feeds("").is_empty());
+	}
+
+	#[test]
+	fn test_strip_tracking_params() {
+		assert_eq!(
+			strip_tracking_params("https://example.com/a?id=1&utm_source=reddit&UTM_Medium=x&q=a%20b&fbclid=abc#top"),
+			"https://example.com/a?id=1&q=a%20b#top"
+		);
+		assert_eq!(strip_tracking_params("https://example.com/?utm_campaign=x&gclid=y"), "https://example.com/");
+		assert_eq!(strip_tracking_params("https://example.com/a#b"), "https://example.com/a#b");
+		assert_eq!(strip_tracking_params("https://example.com/watch?v=abc&utm=1"), "https://example.com/watch?v=abc&utm=1");
+	}
+
+	#[test]
+	fn test_unsupported_kind() {
+		assert_eq!(
+			unsupported_kind(&serde_json::json!({ "tournament_data": { "predictions": [] } })),
+			Some("prediction tournament")
+		);
+		assert_eq!(unsupported_kind(&serde_json::json!({ "discussion_type": "CHAT", "post_hint": "self" })), Some("live chat"));
+		assert_eq!(unsupported_kind(&serde_json::json!({ "post_hint": "hosted:audio" })), Some("rich"));
+		assert_eq!(unsupported_kind(&serde_json::json!({ "post_hint": "image" })), None);
+		assert_eq!(unsupported_kind(&serde_json::json!({ "discussion_type": null })), None);
+	}
+
+	#[test]
+	fn test_media_parse_type() {
+		let post_type = |data: serde_json::Value| block_on(Media::parse(&data)).0;
+
+		// An imgur page link Reddit recognised as an image, without a preview
+		assert_eq!(post_type(serde_json::json!({ "post_hint": "image", "url": "https://imgur.com/abc123" })), "image");
+		assert_eq!(post_type(serde_json::json!({ "url": "https://i.imgur.com/abc123.jpeg?1" })), "image");
+		assert_eq!(post_type(serde_json::json!({ "post_hint": "link", "url": "https://example.com/a.png" })), "link");
+		assert_eq!(post_type(serde_json::json!({ "post_hint": "rich:video", "url": "https://youtu.be/abc" })), "link");
+		assert_eq!(
+			post_type(serde_json::json!({ "post_hint": "hosted:video", "media": { "reddit_video": { "fallback_url": "https://v.redd.it/abc/DASH_720.mp4" } } })),
+			"video"
+		);
+		assert_eq!(
+			post_type(serde_json::json!({ "url": "https://www.reddit.com/gallery/abc", "gallery_data": { "items": [] } })),
+			"gallery"
+		);
+	}
+
+	#[test]
+	fn test_crosspost_parse() {
+		let parent = |parent: serde_json::Value| serde_json::json!({ "crosspost_parent": "t3_abc", "crosspost_parent_list": [parent] });
+
+		let public = Crosspost::parse(&parent(serde_json::json!({ "subreddit": "rust", "subreddit_type": "public", "removed_by_category": null }))).unwrap();
+		assert!(public.available);
+		assert_eq!(public.community, "rust");
+
+		assert!(
+			!Crosspost::parse(&parent(serde_json::json!({ "subreddit": "secret", "subreddit_type": "private" })))
+				.unwrap()
+				.available
+		);
+		assert!(
+			!Crosspost::parse(&parent(serde_json::json!({ "subreddit": "rust", "removed_by_category": "moderator" })))
+				.unwrap()
+				.available
+		);
+		assert!(
+			!Crosspost::parse(&serde_json::json!({ "crosspost_parent": "t3_abc", "crosspost_parent_list": [] }))
+				.unwrap()
+				.available
+		);
+		assert!(Crosspost::parse(&serde_json::json!({ "title": "Not a crosspost" })).is_none());
+
+		// The media of an unavailable original post isn't used
+		let private = parent(serde_json::json!({ "subreddit_type": "private", "url": "https://i.redd.it/abc.png", "post_hint": "image" }));
+		assert_ne!(block_on(Media::parse(&private)).0, "image");
+	}
+
+	#[test]
+	fn test_reading_time() {
+		assert_eq!(reading_time("<p>A short post</p>"), None);
+		assert_eq!(reading_time(&format!("<div class=\"md\"><p>{}</p></div>", "word ".repeat(399))), None);
+		assert_eq!(reading_time(&format!("<div class=\"md\"><p>{}</p></div>", "word ".repeat(400))), Some(2));
+		assert_eq!(reading_time(&"<p>word</p>".repeat(1001)), Some(6));
+	}
+
+	#[test]
+	fn test_highlight_matches() {
+		assert_eq!(
+			highlight_matches(r#"<p>Rust is <a href="/r/rust">rusty</a>, RUST!</p>"#, "rust"),
+			r#"<p><mark>Rust</mark> is <a href="/r/rust"><mark>rust</mark>y</a>, <mark>RUST</mark>!</p>"#
+		);
+		assert_eq!(highlight_matches("<p>Q&amp;A</p>", "q&a"), "<p><mark>Q&amp;A</mark></p>");
+		assert_eq!(highlight_matches("<p>text</p>", "p"), "<p>text</p>");
+		assert_eq!(highlight_matches("<p>Q&amp;A</p>", "amp"), "<p>Q&amp;A</p>");
+		assert_eq!(highlight_matches("<p>&quot;quoted&quot;</p>", "quot"), "<p>&quot;<mark>quot</mark>ed&quot;</p>");
+		assert_eq!(highlight_matches("<p>it&#39;s</p>", "it's"), "<p><mark>it&#39;s</mark></p>");
+		assert_eq!(highlight_matches("<p>&foo; foo</p>", "foo"), "<p>&foo; <mark>foo</mark></p>");
+		assert!(html_contains("<p>Q&amp;A</p>", "Q&A"));
+		assert!(!html_contains(r#"<p><a href="/r/rust">link</a></p>"#, "rust"));
+	}
+
+	#[test]
+	fn test_reddit_path() {
+		assert_eq!(
+			reddit_path("https://www.reddit.com/r/rust/comments/abc123/title/?sort=new").as_deref(),
+			Some("/r/rust/comments/abc123/title/?sort=new")
+		);
+		assert_eq!(reddit_path("old.reddit.com/r/rust").as_deref(), Some("/r/rust"));
+		assert_eq!(reddit_path("https://reddit.com/u/spez/submitted").as_deref(), Some("/user/spez/submitted"));
+		assert_eq!(reddit_path("https://www.reddit.com/user/spez").as_deref(), Some("/user/spez"));
+		assert_eq!(reddit_path("http://np.reddit.com/comments/abc123").as_deref(), Some("/comments/abc123"));
+		assert_eq!(reddit_path("https://redd.it/abc123").as_deref(), Some("/comments/abc123"));
+		assert_eq!(reddit_path("https://www.reddit.com/r/rust/s/AbCdEfGh12").as_deref(), Some("/r/rust/s/AbCdEfGh12"));
+		assert_eq!(reddit_path("https://reddit.com").as_deref(), Some("/"));
+		assert_eq!(reddit_path("https://i.redd.it/abc.jpg").as_deref(), Some("/img/abc.jpg"));
+		assert_eq!(reddit_path("https://example.com/r/rust"), None);
+		assert_eq!(reddit_path("https://notreddit.com/r/rust"), None);
+	}
+
+	#[test]
+	fn test_parse_sub_sorts() {
+		let sorts = parse_sub_sorts("Science=top/week, r/news=new  rust=best+pics=hot/day+news=top ,=new");
+		assert_eq!(
+			sorts,
+			vec![
+				("science".to_string(), "top".to_string(), "week".to_string()),
+				("news".to_string(), "new".to_string(), String::new())
+			]
+		);
+		assert_eq!(format_sub_sorts(&sorts), "science=top/week+news=new");
+		assert!(parse_sub_sorts("").is_empty());
+	}
+
+	#[test]
+	fn test_error_page_under_base_path() {
+		super::BASE_PATH.set("/reddit".to_string()).unwrap();
+		let req = hyper::Request::get("/r/rust/comments/abc").body(hyper::Body::empty()).unwrap();
+		let res = super::error_page(req, 404, "Not found".to_string(), Vec::new()).unwrap();
+		let body = String::from_utf8(block_on(hyper::body::to_bytes(res.into_body())).unwrap().to_vec()).unwrap();
+		assert!(body.contains(r#"href="/reddit/style.css"#));
+		assert!(body.contains(r#"href="/reddit/r/rust/comments""#));
+		assert!(!body.contains(r#"href="/style.css"#));
+	}
+
+	#[test]
+	fn test_parent_path() {
+		assert_eq!(parent_path("/r/rust/comments/abc"), "/r/rust/comments");
+		assert_eq!(parent_path("/r/rust/"), "/r");
+		assert_eq!(parent_path("/about"), "/");
+		assert_eq!(parent_path("/"), "/");
+	}
+
+	#[test]
+	fn test_inline_media() {
+		let metadata = serde_json::json!({
+			"abc123": { "status": "valid", "e": "Image", "s": { "u": "https://preview.redd.it/abc123.png?width=640&format=png&auto=webp&s=x" } },
+			"def456": { "status": "valid", "e": "Image", "s": { "u": "https://preview.redd.it/def456.jpg?width=320&format=pjpg&auto=webp&s=y" } }
+		});
+		let body = r#"<p><a href="https://preview.redd.it/abc123.png?width=640">https://preview.redd.it/abc123.png?width=640</a></p><p><a href="https://preview.redd.it/def456.jpg?width=320">A cat</a></p><p><a href="https://preview.redd.it/zzz999.png">other</a></p>"#;
+		let inlined = inline_media(body, &metadata);
+		assert!(inlined.contains(r#"src="/preview/pre/abc123.png?width=640&format=png&auto=webp&s=x" alt="Image"></a></figure>"#));
+		assert!(inlined.contains(r#"alt="A cat"></a><figcaption>A cat</figcaption></figure>"#));
+		assert!(inlined.contains(r#"<a href="https://preview.redd.it/zzz999.png">other</a>"#));
+		assert_eq!(inline_media(body, &serde_json::Value::Null), body);
+	}
 
 	#[test]
 	fn format_num_works() {
@@ -1039,6 +2319,9 @@ mod tests {
 		assert_eq!(format_num(1999), ("2.0k".to_string(), "1999".to_string()));
 		assert_eq!(format_num(1001), ("1.0k".to_string(), "1001".to_string()));
 		assert_eq!(format_num(1_999_999), ("2.0m".to_string(), "1999999".to_string()));
+		assert_eq!(format_num(999_960), ("1.0m".to_string(), "999960".to_string()));
+		assert_eq!(format_num(-1234), ("-1.2k".to_string(), "-1234".to_string()));
+		assert_eq!(format_num(0), ("0".to_string(), "0".to_string()));
 	}
 
 	#[test]
@@ -1079,4 +2362,17 @@ mod tests {
 		assert_eq!(format_url("nsfw"), "");
 		assert_eq!(format_url("spoiler"), "");
 	}
+
+	#[test]
+	fn test_record_upstream() {
+		let down: Result<(), FetchError> = Err(FetchError::new(502, "Couldn't send request to Reddit: connection refused"));
+		for _ in 0..3 {
+			record_upstream(&down);
+		}
+		assert!(reddit_degraded());
+		record_upstream::<()>(&Err(FetchError::new(429, "Too many requests.")));
+		assert!(reddit_degraded());
+		record_upstream(&Ok(()));
+		assert!(!reddit_degraded());
+	}
 }
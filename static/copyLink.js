@@ -0,0 +1,24 @@
+// @license http://www.gnu.org/licenses/agpl-3.0.html AGPL-3.0
+(function () {
+    // Without a secure context there's no clipboard, so the links just open the comment
+    if (!navigator.clipboard) {
+        return;
+    }
+
+    document.addEventListener("click", function (event) {
+        var link = event.target.closest("a.copy_link");
+        if (!link) {
+            return;
+        }
+
+        event.preventDefault();
+        navigator.clipboard.writeText(link.href).then(function () {
+            var label = link.textContent;
+            link.textContent = "copied";
+            setTimeout(function () {
+                link.textContent = label;
+            }, 1500);
+        });
+    });
+})();
+// @license-end
@@ -71,6 +71,7 @@
         var videos = document.querySelectorAll("video.hls_autoplay");
         videos.forEach(function (video) {
             video.setAttribute("autoplay", "");
+            video.muted = true;
         });
     }
 })();